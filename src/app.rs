@@ -1,23 +1,79 @@
 use std::{
-    path::{Path, PathBuf},
     collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
 };
+use chrono::NaiveDate;
+use crossbeam_channel::{bounded, Receiver, TryRecvError};
 use eframe::egui::{self, ScrollArea, ProgressBar, Ui};
+use regex::Regex;
 use rfd::FileDialog;
 use crate::{
+    batch::{self, BatchAction, BatchOptions, Selection},
+    config::Config,
     file_scanner::{Scanner, ScannerMessage},
-    file_utils::{create_hardlink, move_file},
+    file_utils::{format_duration, format_hash, format_size, sort_groups, GroupSort, ScanResult, ScanRoot},
+    filters::{FileFilter, FilterRule, RuleAction, RuleKind, SymlinkPolicy},
+    hash_cache::{CacheStats, HashCache},
+    hasher::HashAlgorithm,
+    ignore_files,
+    journal::Journal,
+    keep::KeepRule,
+    plan::{ActionPlan, Operation, PlanOutcome},
     preview::Preview,
+    report::ScanReport,
+    scan_handle::{ScanHandle, ScanProgress},
 };
 
+struct RootEntry {
+    path: String,
+    enabled: bool,
+    /// Files under a reference root are matched against but never touched.
+    reference: bool,
+}
+
+/// Text being edited for constraints that are not plain numbers.
+#[derive(Default)]
+struct ConstraintInput {
+    modified_after: String,
+    modified_before: String,
+    mode: String,
+}
+
+/// A plan running on a worker thread. The thread holds the journal until
+/// it is done and sends it back with the outcome.
+struct Execution {
+    steps: usize,
+    rx: Receiver<(Journal, io::Result<PlanOutcome>)>,
+}
+
 pub struct DuplicateFinderApp {
-    directory: String,
-    filters: Vec<String>,
-    new_filter: String,
-    duplicates: HashMap<Vec<u8>, Vec<PathBuf>>,
+    roots: Vec<RootEntry>,
+    new_rule: FilterRule,
+    constraint_input: ConstraintInput,
+    config: Config,
+    result: ScanResult,
+    group_sort: GroupSort,
+    /// Keepers picked by hand, by group ID. They survive policy changes.
+    keeper_overrides: HashMap<u64, PathBuf>,
+    selection: Selection,
+    select_pattern: String,
+    batch_action: BatchAction,
+    batch_options: BatchOptions,
+    /// Queue per-file actions into the plan instead of running them.
+    dry_run: bool,
+    /// Actions waiting for review, shown until executed or discarded.
+    plan: Option<ActionPlan>,
+    cache: Option<Arc<HashCache>>,
+    cache_entries: Option<u64>,
+    journal: Option<Journal>,
+    execution: Option<Execution>,
     scanner: Scanner,
+    scan_handle: Option<ScanHandle>,
     scanning: bool,
-    progress: f32,
+    progress: ScanProgress,
     selected_file: Option<PathBuf>,
     preview: Option<Preview>,
     error: Option<String>,
@@ -25,18 +81,58 @@ pub struct DuplicateFinderApp {
 
 impl DuplicateFinderApp {
     pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
-        Self {
-            directory: String::new(),
-            filters: Vec::new(),
-            new_filter: String::new(),
-            duplicates: HashMap::new(),
-            scanner: Scanner::new(),
+        let (cache, mut error) = match HashCache::open_default() {
+            Ok(cache) => (Some(Arc::new(cache)), None),
+            Err(e) => (None, Some(format!("Hash cache unavailable: {}", e))),
+        };
+        let journal = match Journal::open_default() {
+            Ok(journal) => Some(journal),
+            Err(e) => {
+                error.get_or_insert(format!("Undo journal unavailable, file actions are disabled: {}", e));
+                None
+            }
+        };
+        let mut scanner = Scanner::new();
+        scanner.set_cache(cache.clone());
+
+        let mut app = Self {
+            roots: vec![RootEntry { path: String::new(), enabled: true, reference: false }],
+            new_rule: FilterRule::exclude(RuleKind::Extension, ""),
+            constraint_input: ConstraintInput::default(),
+            config: Config::load(),
+            result: ScanResult::default(),
+            group_sort: GroupSort::default(),
+            keeper_overrides: HashMap::new(),
+            selection: Selection::default(),
+            select_pattern: String::new(),
+            batch_action: BatchAction::Delete,
+            batch_options: BatchOptions::default(),
+            dry_run: false,
+            plan: None,
+            cache,
+            cache_entries: None,
+            journal,
+            execution: None,
+            scanner,
+            scan_handle: None,
             scanning: false,
-            progress: 0.0,
+            progress: ScanProgress::default(),
             selected_file: None,
             preview: None,
-            error: None,
-        }
+            error,
+        };
+        let constraints = &app.config.filters.constraints;
+        app.constraint_input = ConstraintInput {
+            modified_after: constraints.modified_after.map(|d| d.to_string()).unwrap_or_default(),
+            modified_before: constraints.modified_before.map(|d| d.to_string()).unwrap_or_default(),
+            mode: constraints.mode.map(|m| format!("{:o}", m)).unwrap_or_default(),
+        };
+        app.refresh_cache_entries();
+        app
+    }
+
+    fn refresh_cache_entries(&mut self) {
+        self.cache_entries = self.cache.as_ref().and_then(|cache| cache.entry_count().ok());
     }
 
     fn handle_scanner_messages(&mut self) {
@@ -45,9 +141,21 @@ impl DuplicateFinderApp {
                 ScannerMessage::Progress(progress) => {
                     self.progress = progress;
                 }
-                ScannerMessage::Found(duplicates) => {
-                    self.duplicates = duplicates;
+                ScannerMessage::Problem(problem) => {
+                    // The final result carries every problem, don't add late ones twice
+                    if self.scanning {
+                        self.result.problems.push(problem);
+                    }
+                }
+                ScannerMessage::Changed => {
+                    self.error = Some("Files changed since the last scan, rescan to refresh the results".to_string());
+                }
+                ScannerMessage::Found(result) => {
+                    self.result = *result;
+                    sort_groups(&mut self.result.duplicates, self.group_sort);
+                    self.apply_keep_policy();
                     self.scanning = false;
+                    self.refresh_cache_entries();
                 }
                 ScannerMessage::Error(error) => {
                     self.error = Some(error);
@@ -57,61 +165,784 @@ impl DuplicateFinderApp {
         }
     }
 
+    fn apply_keep_policy(&mut self) {
+        if let Err(e) = self.config.keep.apply(&mut self.result.duplicates) {
+            self.error = Some(format!("Invalid keep pattern: {}", e));
+        }
+        for group in &mut self.result.duplicates {
+            let overridden = self.keeper_overrides.get(&group.id)
+                .and_then(|path| group.members.iter().position(|member| &member.path == path));
+            if let Some(idx) = overridden {
+                group.keeper = idx;
+            }
+        }
+    }
+
+    fn show_keep_policy(&mut self, ui: &mut Ui) {
+        let previous = self.config.keep.clone();
+        let keep = &mut self.config.keep;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Keep")
+                .selected_text(keep.rule.label())
+                .show_ui(ui, |ui| {
+                    for rule in KeepRule::ALL {
+                        ui.selectable_value(&mut keep.rule, rule, rule.label());
+                    }
+                });
+            match keep.rule {
+                KeepRule::MatchingPattern => {
+                    ui.text_edit_singleline(&mut keep.pattern);
+                }
+                KeepRule::PreferredDirectories => {
+                    let mut to_remove = None;
+                    for (idx, dir) in keep.preferred_dirs.iter().enumerate() {
+                        ui.label(format!("{}. {}", idx + 1, dir.display()));
+                        if ui.small_button("❌").clicked() {
+                            to_remove = Some(idx);
+                        }
+                    }
+                    if let Some(idx) = to_remove {
+                        keep.preferred_dirs.remove(idx);
+                    }
+                    if ui.small_button("➕").clicked() {
+                        if let Some(path) = FileDialog::new().pick_folder() {
+                            keep.preferred_dirs.push(path);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        });
+        if self.config.keep != previous {
+            self.error = None;
+            self.apply_keep_policy();
+        }
+    }
+
+    fn show_batch_controls(&mut self, ui: &mut Ui) {
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Select:");
+            if ui.button("All except keepers").clicked() {
+                self.selection.select_where(&self.result.duplicates, |_| true);
+            }
+            if ui.button("In directory…").clicked() {
+                if let Some(dir) = FileDialog::new().pick_folder() {
+                    self.selection.select_where(&self.result.duplicates, |member| member.path.starts_with(&dir));
+                }
+            }
+            ui.text_edit_singleline(&mut self.select_pattern);
+            if ui.button("Matching regex").clicked() {
+                match Regex::new(&self.select_pattern) {
+                    Ok(pattern) => self.selection.select_where(&self.result.duplicates, |member| {
+                        pattern.is_match(&member.path.to_string_lossy())
+                    }),
+                    Err(e) => self.error = Some(format!("Invalid selection pattern: {}", e)),
+                }
+            }
+            if ui.button("Clear").clicked() {
+                self.selection.clear();
+            }
+        });
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("batch_action")
+                .selected_text(self.batch_action.label())
+                .show_ui(ui, |ui| {
+                    for action in BatchAction::ALL {
+                        ui.selectable_value(&mut self.batch_action, action, action.label());
+                    }
+                });
+            if self.batch_action == BatchAction::Move {
+                let folder = self.batch_options.destination.as_ref()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_else(|| "Choose folder…".to_string());
+                if ui.button(folder).clicked() {
+                    if let Some(path) = FileDialog::new().pick_folder() {
+                        self.batch_options.destination = Some(path);
+                    }
+                }
+            }
+            let targets = self.selection.targets(&self.result.duplicates, &self.result.roots);
+            let ready = !targets.is_empty()
+                && (self.batch_action != BatchAction::Move || self.batch_options.destination.is_some());
+            if ui.add_enabled(ready, egui::Button::new(format!("Plan {} selected", targets.len()))).clicked() {
+                match batch::plan(self.batch_action, &targets, &self.batch_options) {
+                    Ok(plan) => self.plan.get_or_insert_with(ActionPlan::default).steps.extend(plan.steps),
+                    Err(e) => self.error = Some(e.to_string()),
+                }
+            }
+            ui.checkbox(&mut self.dry_run, "🧪 Dry run")
+                .on_hover_text("Add single-file actions to the plan instead of running them");
+            ui.checkbox(&mut self.batch_options.preserve_permissions, "Hardlinks keep the duplicate's permissions")
+                .on_hover_text("The kept copy shares the linked file, so it gets the same mode and owner. Reference copies are never changed");
+            ui.checkbox(&mut self.batch_options.relative_symlinks, "Relative symlinks")
+                .on_hover_text("Links keep working when the folder holding both copies is moved");
+        });
+    }
+
+    fn show_plan_window(&mut self, ctx: &egui::Context) {
+        let Some(plan) = &self.plan else {
+            return;
+        };
+        let mut execute = false;
+        let mut discard = false;
+        egui::Window::new("📋 Action Plan")
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "{} steps, reclaiming {}",
+                    plan.steps.len(),
+                    format_size(plan.reclaimed()),
+                ));
+                ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                    egui::Grid::new("plan_grid").striped(true).show(ui, |ui| {
+                        for (idx, step) in plan.steps.iter().enumerate() {
+                            ui.label(format!("{}.", idx + 1));
+                            ui.label(step.operation.label());
+                            ui.label(step.path.display().to_string());
+                            match &step.operation {
+                                Operation::Delete | Operation::Trash => ui.label(""),
+                                Operation::Hardlink { keeper, .. }
+                                | Operation::Reflink { keeper }
+                                | Operation::Symlink { keeper, .. } => {
+                                    ui.label(format!("→ {}", keeper.display()))
+                                }
+                                Operation::Move { destination } => ui.label(format!("→ {}", destination.display())),
+                            };
+                            ui.label(format_size(step.reclaimed()));
+                            ui.end_row();
+                        }
+                    });
+                });
+                ui.horizontal(|ui| {
+                    execute = ui.button("▶ Execute").clicked();
+                    if ui.button("💾 Export…").clicked() {
+                        if let Some(path) = FileDialog::new()
+                            .set_file_name("dupfi-plan.json")
+                            .add_filter("JSON", &["json"])
+                            .save_file() {
+                            if let Err(e) = plan.write_to(&path) {
+                                self.error = Some(format!("Could not export plan: {}", e));
+                            }
+                        }
+                    }
+                    discard = ui.button("Discard").clicked();
+                });
+            });
+
+        if execute {
+            if let Some(plan) = self.plan.take() {
+                self.execute_plan(plan);
+            }
+        } else if discard {
+            self.plan = None;
+        }
+    }
+
+    fn load_plan(&mut self) {
+        if let Some(path) = FileDialog::new().add_filter("JSON", &["json"]).pick_file() {
+            match ActionPlan::read_from(&path) {
+                Ok(plan) => self.plan = Some(plan),
+                Err(e) => self.error = Some(format!("Could not load plan: {}", e)),
+            }
+        }
+    }
+
+    /// Runs the steps right away or, in dry-run mode, adds them to the plan.
+    fn run_or_queue(&mut self, steps: ActionPlan) {
+        if self.dry_run {
+            self.plan.get_or_insert_with(ActionPlan::default).steps.extend(steps.steps);
+        } else {
+            self.execute_plan(steps);
+        }
+    }
+
+    /// Plans only run through the journal, so that they can be undone.
+    fn execute_plan(&mut self, plan: ActionPlan) {
+        if self.execution.is_some() {
+            self.error = Some("Another plan is still running, no files were changed".to_string());
+            return;
+        }
+        let Some(mut journal) = self.journal.take() else {
+            self.error = Some("Undo journal unavailable, no files were changed".to_string());
+            return;
+        };
+        let (tx, rx) = bounded(1);
+        self.execution = Some(Execution { steps: plan.steps.len(), rx });
+        std::thread::spawn(move || {
+            let outcome = journal.execute(&plan);
+            let _ = tx.send((journal, outcome));
+        });
+    }
+
+    fn handle_execution(&mut self) {
+        let Some(execution) = &self.execution else {
+            return;
+        };
+        let steps = execution.steps;
+        match execution.rx.try_recv() {
+            Ok((journal, outcome)) => {
+                self.execution = None;
+                self.journal = Some(journal);
+                match outcome {
+                    Ok(outcome) => self.apply_outcome(outcome, steps),
+                    Err(e) => self.error = Some(format!("Could not prepare the quarantine: {}", e)),
+                }
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => {
+                self.execution = None;
+                self.error = Some("Executing the plan failed, restart to reload the undo journal".to_string());
+            }
+        }
+    }
+
+    fn apply_outcome(&mut self, outcome: PlanOutcome, total: usize) {
+        if !outcome.errors.is_empty() {
+            self.error = Some(format!(
+                "{} of {} files failed:\n{}",
+                outcome.errors.len(),
+                total,
+                outcome.errors.join("\n"),
+            ));
+        }
+        batch::forget(&mut self.result.duplicates, &outcome.done);
+        for path in &outcome.done {
+            self.selection.set(path, false);
+        }
+    }
+
+    fn enabled_roots(&self) -> Vec<ScanRoot> {
+        self.roots.iter()
+            .filter(|root| root.enabled && !root.path.is_empty())
+            .map(|root| ScanRoot::new(&root.path, root.reference))
+            .collect()
+    }
+
     fn show_directory_section(&mut self, ui: &mut Ui) {
+        let mut to_remove = None;
+        let removable = self.roots.len() > 1;
+        for (idx, root) in self.roots.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut root.enabled, "");
+                if ui.button("📁 Select Directory").clicked() {
+                    if let Some(path) = FileDialog::new().pick_folder() {
+                        root.path = path.display().to_string();
+                    }
+                }
+                ui.text_edit_singleline(&mut root.path);
+                ui.checkbox(&mut root.reference, "🔒 Reference")
+                    .on_hover_text("Match against these files but never offer them for deletion");
+                if removable && ui.small_button("❌").clicked() {
+                    to_remove = Some(idx);
+                }
+            });
+        }
+        if let Some(idx) = to_remove {
+            self.roots.remove(idx);
+        }
         ui.horizontal(|ui| {
-            if ui.button("📁 Select Directory").clicked() {
+            if ui.button("➕ Add Directory").clicked() {
                 if let Some(path) = FileDialog::new().pick_folder() {
-                    self.directory = path.display().to_string();
+                    self.roots.push(RootEntry { path: path.display().to_string(), enabled: true, reference: false });
                 }
             }
-            ui.text_edit_singleline(&mut self.directory);
+            if ui.button("🔒 Add Reference Directory").clicked() {
+                if let Some(path) = FileDialog::new().pick_folder() {
+                    self.roots.push(RootEntry { path: path.display().to_string(), enabled: true, reference: true });
+                }
+            }
+            if ui.button("📂 Load Plan…").clicked() {
+                self.load_plan();
+            }
         });
 
-        if !self.directory.is_empty() && !self.scanning {
+        if !self.enabled_roots().is_empty() && !self.scanning {
             if ui.button("🔍 Start Scan").clicked() {
                 self.start_scan();
             }
         }
 
         if self.scanning {
-            ui.add(ProgressBar::new(self.progress).text("Scanning..."));
+            let paused = self.scan_handle.as_ref().is_some_and(|handle| handle.is_paused());
+            self.show_progress(ui, paused);
+            ui.horizontal(|ui| {
+                if let Some(handle) = &self.scan_handle {
+                    if paused {
+                        if ui.button("▶ Resume").clicked() {
+                            handle.resume();
+                        }
+                    } else if ui.button("⏸ Pause").clicked() {
+                        handle.pause();
+                    }
+                }
+                if ui.button("⏹ Cancel").clicked() {
+                    self.scanner.cancel();
+                    self.scan_handle = None;
+                    self.scanning = false;
+                }
+            });
+        }
+    }
+
+    fn show_progress(&self, ui: &mut Ui, paused: bool) {
+        let progress = &self.progress;
+        let status = if paused { "Paused" } else { progress.phase.label() };
+        let unknown_total = progress.files_total == 0 && progress.bytes_total == 0;
+        ui.add(
+            ProgressBar::new(progress.fraction())
+                .text(format!("{}: {:.0}%", status, progress.fraction() * 100.0))
+                .animate(unknown_total && !paused),
+        );
+
+        let mut details = if progress.files_total > 0 {
+            format!("{} / {} files", progress.files_done, progress.files_total)
+        } else {
+            format!("{} files", progress.files_done)
+        };
+        if progress.bytes_total > 0 {
+            details.push_str(&format!(
+                ", {} / {} at {}/s",
+                format_size(progress.bytes_done),
+                format_size(progress.bytes_total),
+                format_size(progress.throughput as u64),
+            ));
+        }
+        if let Some(eta) = progress.eta {
+            details.push_str(&format!(", about {} left", format_duration(eta)));
         }
+        ui.label(details);
     }
 
     fn show_filters_section(&mut self, ui: &mut Ui) {
         ui.collapsing("🔧 Filters", |ui| {
             ui.horizontal(|ui| {
-                ui.label("Exclude extension:");
-                if ui.text_edit_singleline(&mut self.new_filter).lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-                    if !self.new_filter.is_empty() {
-                        self.filters.push(self.new_filter.clone());
-                        self.new_filter.clear();
-                    }
+                let rule = &mut self.new_rule;
+                egui::ComboBox::from_id_source("rule_action")
+                    .selected_text(format!("{:?}", rule.action))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut rule.action, RuleAction::Exclude, "Exclude");
+                        ui.selectable_value(&mut rule.action, RuleAction::Include, "Include");
+                    });
+                egui::ComboBox::from_id_source("rule_kind")
+                    .selected_text(rule.kind.label())
+                    .show_ui(ui, |ui| {
+                        for kind in RuleKind::ALL {
+                            ui.selectable_value(&mut rule.kind, kind, kind.label());
+                        }
+                    });
+                let response = ui.text_edit_singleline(&mut rule.pattern);
+                let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if (submitted || ui.button("➕ Add").clicked()) && !rule.pattern.trim().is_empty() {
+                    self.config.filters.rules.push(rule.clone());
+                    rule.pattern.clear();
                 }
             });
-
+            ui.checkbox(&mut self.config.filters.case_sensitive, "Case sensitive");
+            ui.checkbox(&mut self.config.filters.same_file_system, "Stay on the file system of each root");
             ui.horizontal_wrapped(|ui| {
+                ui.label("Skip mount points:");
+                let mounts = &mut self.config.filters.excluded_mounts;
                 let mut to_remove = None;
-                for (idx, filter) in self.filters.iter().enumerate() {
-                    ui.label(format!(".{}", filter));
+                for (idx, mount) in mounts.iter().enumerate() {
+                    ui.label(mount.display().to_string());
                     if ui.small_button("❌").clicked() {
                         to_remove = Some(idx);
                     }
                 }
                 if let Some(idx) = to_remove {
-                    self.filters.remove(idx);
+                    mounts.remove(idx);
+                }
+                if ui.small_button("➕").clicked() {
+                    if let Some(path) = FileDialog::new().pick_folder() {
+                        mounts.push(path);
+                    }
                 }
             });
+            let symlinks = &mut self.config.filters.symlinks;
+            egui::ComboBox::from_label("Symbolic links")
+                .selected_text(symlinks.label())
+                .show_ui(ui, |ui| {
+                    for policy in SymlinkPolicy::ALL {
+                        ui.selectable_value(symlinks, policy, policy.label());
+                    }
+                });
+
+            let mut to_remove = None;
+            for (idx, rule) in self.config.filters.rules.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let sign = match rule.action {
+                        RuleAction::Include => "➕",
+                        RuleAction::Exclude => "➖",
+                    };
+                    ui.label(format!("{} {}: {}", sign, rule.kind.label(), rule.pattern));
+                    if ui.small_button("❌").clicked() {
+                        to_remove = Some(idx);
+                    }
+                });
+            }
+            if let Some(idx) = to_remove {
+                self.config.filters.rules.remove(idx);
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                let sources = &mut self.config.filters.ignore_files;
+                ui.label("Honor ignore files:");
+                ui.checkbox(&mut sources.gitignore, ".gitignore");
+                ui.checkbox(&mut sources.dot_ignore, ".ignore");
+                ui.checkbox(&mut sources.dupfiignore, ignore_files::DUPFI_IGNORE);
+                let global = ui.checkbox(&mut sources.global, "Global");
+                if let Some(path) = ignore_files::global_path() {
+                    global.on_hover_text(path.display().to_string());
+                }
+            });
+
+            ui.separator();
+            let constraints = &mut self.config.filters.constraints;
+            let input = &mut self.constraint_input;
+            optional_size(ui, "Minimum size (KiB)", &mut constraints.min_size);
+            optional_size(ui, "Maximum size (KiB)", &mut constraints.max_size);
+            date_edit(ui, "Modified on or after:", &mut input.modified_after, &mut constraints.modified_after);
+            date_edit(ui, "Modified before:", &mut input.modified_before, &mut constraints.modified_before);
+            optional_id(ui, "Owner UID", &mut constraints.owner);
+            optional_id(ui, "Group GID", &mut constraints.group);
+            ui.horizontal(|ui| {
+                ui.label("Required permission bits (octal):");
+                let response = ui.add(egui::TextEdit::singleline(&mut input.mode).hint_text("644").desired_width(60.0));
+                if response.changed() {
+                    constraints.mode = u32::from_str_radix(input.mode.trim(), 8).ok();
+                }
+                if !input.mode.trim().is_empty() && constraints.mode.is_none() {
+                    ui.colored_label(egui::Color32::RED, "invalid");
+                }
+            });
+            ui.checkbox(&mut constraints.skip_hidden, "Skip hidden files and directories");
+        });
+    }
+
+    fn show_hashing_section(&mut self, ui: &mut Ui) {
+        ui.collapsing("⚙ Hashing", |ui| {
+            let algorithm = &mut self.config.hash.algorithm;
+            egui::ComboBox::from_label("Algorithm")
+                .selected_text(algorithm.label())
+                .show_ui(ui, |ui| {
+                    for candidate in HashAlgorithm::ALL {
+                        ui.selectable_value(algorithm, candidate, candidate.label());
+                    }
+                });
+            if !algorithm.is_cryptographic() {
+                ui.label("⚡ Fast non-cryptographic hash, best suited for trusted local data");
+            }
+            ui.horizontal(|ui| {
+                ui.label("Chunk size (KiB):");
+                let mut chunk_kib = self.config.hash.chunk_size / 1024;
+                if ui.add(egui::DragValue::new(&mut chunk_kib).clamp_range(4..=65536)).changed() {
+                    self.config.hash.chunk_size = chunk_kib * 1024;
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Memory budget (MiB):");
+                let mut budget_mib = self.config.hash.memory_budget / (1024 * 1024);
+                if ui.add(egui::DragValue::new(&mut budget_mib).clamp_range(1..=4096)).changed() {
+                    self.config.hash.memory_budget = budget_mib * 1024 * 1024;
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Partial hash size (KiB):");
+                let mut partial_kib = self.config.hash.partial_size / 1024;
+                if ui.add(egui::DragValue::new(&mut partial_kib).clamp_range(1..=1024)).changed() {
+                    self.config.hash.partial_size = partial_kib * 1024;
+                }
+            });
+            ui.checkbox(&mut self.config.hash.verify, "Verify groups byte-for-byte (paranoid mode)");
+
+            ui.separator();
+            let Some(cache) = self.cache.clone() else {
+                ui.label("Hash cache unavailable");
+                return;
+            };
+            ui.checkbox(&mut self.config.hash.use_cache, "Use hash cache");
+            ui.add_enabled(
+                self.config.hash.use_cache,
+                egui::Checkbox::new(&mut self.config.hash.force_rehash, "Force rehash on next scan"),
+            );
+            ui.label(format!(
+                "Cache: {} entries in {}",
+                self.cache_entries.map_or("?".to_string(), |count| count.to_string()),
+                cache.path().display(),
+            ));
+            ui.horizontal(|ui| {
+                if ui.button("🧹 Prune cache").clicked() {
+                    match cache.prune() {
+                        Ok(removed) => self.error = Some(format!("Pruned {} stale cache entries", removed)),
+                        Err(e) => self.error = Some(e.to_string()),
+                    }
+                    self.refresh_cache_entries();
+                }
+                if ui.button("❌ Clear cache").clicked() {
+                    if let Err(e) = cache.clear() {
+                        self.error = Some(e.to_string());
+                    }
+                    self.refresh_cache_entries();
+                }
+            });
+        });
+    }
+
+    fn show_statistics_section(&mut self, ui: &mut Ui) {
+        ui.collapsing("📊 Statistics", |ui| {
+            egui::Grid::new("stage_stats").striped(true).show(ui, |ui| {
+                ui.label("Stage");
+                ui.label("Candidates");
+                ui.label("Remaining");
+                ui.label("Read");
+                ui.label("Saved");
+                ui.end_row();
+
+                for stats in &self.result.stages {
+                    ui.label(stats.stage.label());
+                    ui.label(stats.candidates.to_string());
+                    ui.label(stats.remaining.to_string());
+                    ui.label(format_size(stats.bytes_read));
+                    ui.label(format_size(stats.bytes_saved));
+                    ui.end_row();
+                }
+            });
+            if self.result.devices.len() > 1 || self.result.devices.iter().any(|device| device.reflink) {
+                ui.label("Per device (hard links and reflinks only reclaim space within a device):");
+                egui::Grid::new("device_stats").striped(true).show(ui, |ui| {
+                    ui.label("Device");
+                    ui.label("Files");
+                    ui.label("Duplicates");
+                    ui.label("Groups");
+                    ui.label("Reclaimable");
+                    ui.label("Reflinks");
+                    ui.end_row();
+
+                    for device in &self.result.devices {
+                        ui.label(format!("#{}", device.device))
+                            .on_hover_text(format!("e.g. {}", device.example.display()));
+                        ui.label(device.files.to_string());
+                        ui.label(device.duplicate_files.to_string());
+                        ui.label(device.groups.to_string());
+                        ui.label(format_size(device.reclaimable));
+                        if device.reflink {
+                            ui.label("✔");
+                        } else {
+                            ui.label("—").on_hover_text("Not supported here, use hard links or symbolic links");
+                        }
+                        ui.end_row();
+                    }
+                });
+            }
+            let cache_stats = self.result.cache_stats;
+            if cache_stats != CacheStats::default() {
+                ui.label(format!(
+                    "Hash cache: {} hits, {} misses",
+                    cache_stats.hits, cache_stats.misses,
+                ));
+            }
+            if ui.button("💾 Export Report").clicked() {
+                self.export_report();
+            }
+        });
+    }
+
+    fn export_report(&mut self) {
+        if let Some(path) = FileDialog::new()
+            .set_file_name("dupfi-report.json")
+            .add_filter("JSON", &["json"])
+            .save_file() {
+            if let Err(e) = ScanReport::new(&self.result).write_to(&path) {
+                self.error = Some(format!("Could not write report: {}", e));
+            }
+        }
+    }
+
+    fn show_collisions_section(&mut self, ui: &mut Ui) {
+        ui.collapsing(format!("⚠ Hash collisions ({})", self.result.collisions.len()), |ui| {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                "These files share a hash but differ in content. Variants with several identical files are listed as separate groups below.",
+            );
+            for collision in &self.result.collisions {
+                ui.collapsing(format!("Hash {}", format_hash(&collision.hash)), |ui| {
+                    for (idx, group) in collision.groups.iter().enumerate() {
+                        ui.label(format!("Content variant {}:", idx + 1));
+                        for path in group {
+                            ui.label(format!("    {}", path.display()));
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    fn show_hardlinks_section(&mut self, ui: &mut Ui) {
+        ui.collapsing(format!("🔗 Existing hard links ({})", self.result.hardlinks.len()), |ui| {
+            ui.label("These paths already share their data and need no deduplication.");
+            let mut clusters: Vec<_> = self.result.hardlinks.iter().collect();
+            clusters.sort();
+            for (path, links) in clusters {
+                ui.label(path.display().to_string());
+                for link in links {
+                    ui.label(format!("    {}", link.display()));
+                }
+            }
+        });
+    }
+
+    fn show_symlinks_section(&mut self, ui: &mut Ui) {
+        ui.collapsing(format!("↪ Symbolic links ({})", self.result.symlinks.len()), |ui| {
+            ui.label("Links are listed apart from their targets and never treated as duplicates.");
+            let mut links: Vec<_> = self.result.symlinks.iter().collect();
+            links.sort();
+            for (link, target) in links {
+                ui.label(format!("{} → {}", link.display(), target.display()));
+            }
+        });
+    }
+
+    fn show_deduplicated_section(&mut self, ui: &mut Ui) {
+        ui.collapsing(format!("✅ Deduplicated symlinks ({})", self.result.deduplicated.len()), |ui| {
+            ui.label("These links replaced duplicates earlier and still point to the kept copy.");
+            let mut links: Vec<_> = self.result.deduplicated.iter().collect();
+            links.sort();
+            for (link, keeper) in links {
+                ui.label(format!("{} → {}", link.display(), keeper.display()));
+            }
+        });
+    }
+
+    fn show_history_section(&mut self, ui: &mut Ui) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        let mut undo = None;
+        ui.collapsing("🕘 History", |ui| {
+            ScrollArea::vertical().id_source("history").max_height(200.0).show(ui, |ui| {
+                egui::Grid::new("history").striped(true).show(ui, |ui| {
+                    for batch in journal.batches().iter().rev() {
+                        ui.label(batch.executed_at.format("%Y-%m-%d %H:%M:%S").to_string());
+                        let mut operations: Vec<&str> = batch.entries.iter().map(|entry| entry.step.operation.label()).collect();
+                        operations.dedup();
+                        ui.label(format!("{} × {}", batch.entries.len(), operations.join(", ")))
+                            .on_hover_text(batch.entries.iter()
+                                .map(|entry| entry.step.path.display().to_string())
+                                .collect::<Vec<_>>()
+                                .join("\n"));
+                        ui.label(format_size(batch.reclaimed()));
+                        if batch.is_undone() {
+                            ui.label("Undone");
+                        } else if !batch.can_undo() {
+                            ui.label("Purged");
+                        } else if ui.button("↩ Undo").clicked() {
+                            undo = Some(batch.id);
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+            ui.horizontal(|ui| {
+                ui.label(format!("Quarantine holds {}", format_size(journal.quarantine_size())));
+                if ui.button("🧹 Purge Quarantine").clicked() {
+                    if let Err(e) = journal.purge() {
+                        self.error = Some(format!("Could not purge the quarantine: {}", e));
+                    }
+                }
+            });
+        });
+
+        if let Some(id) = undo {
+            self.error = Some(match journal.undo(id) {
+                Ok(errors) if errors.is_empty() => "Batch undone, rescan to refresh the results".to_string(),
+                Ok(errors) => format!("{} files could not be restored:\n{}", errors.len(), errors.join("\n")),
+                Err(e) => e.to_string(),
+            });
+        }
+    }
+
+    fn show_problems_section(&mut self, ui: &mut Ui) {
+        ui.collapsing(format!("⚠ Problems ({})", self.result.problems.len()), |ui| {
+            ui.label("These files or directories were skipped.");
+            ScrollArea::vertical().id_source("problems").max_height(200.0).show(ui, |ui| {
+                egui::Grid::new("problems").striped(true).show(ui, |ui| {
+                    ui.label("Phase");
+                    ui.label("Problem");
+                    ui.label("Path");
+                    ui.label("Details");
+                    ui.end_row();
+
+                    for problem in &self.result.problems {
+                        ui.label(problem.phase.label());
+                        ui.label(problem.kind.label());
+                        ui.label(problem.path.as_ref().map(|path| path.display().to_string()).unwrap_or_default());
+                        ui.label(&problem.message);
+                        ui.end_row();
+                    }
+                });
+            });
         });
     }
 
     fn show_duplicates_section(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!("{} duplicate groups", self.result.duplicates.len()));
+            let previous = self.group_sort;
+            egui::ComboBox::from_label("Sort by")
+                .selected_text(self.group_sort.label())
+                .show_ui(ui, |ui| {
+                    for order in GroupSort::ALL {
+                        ui.selectable_value(&mut self.group_sort, order, order.label());
+                    }
+                });
+            if self.group_sort != previous {
+                sort_groups(&mut self.result.duplicates, self.group_sort);
+            }
+        });
+        self.show_keep_policy(ui);
+        self.show_batch_controls(ui);
+
+        let mut new_keeper = None;
+        let mut single = ActionPlan::default();
         ScrollArea::vertical().show(ui, |ui| {
-            for (_, paths) in &self.duplicates {
-                if let Some(original) = paths.first() {
-                    ui.collapsing(format!("📄 {}", original.display()), |ui| {
-                        for path in paths {
+            for (group_idx, group) in self.result.duplicates.iter().enumerate() {
+                if let Some(original) = group.members.get(group.keeper).map(|member| &member.path) {
+                    let header = format!(
+                        "📄 {} ({} copies of {}, {} wasted)",
+                        original.display(),
+                        group.members.len(),
+                        format_size(group.size),
+                        format_size(group.wasted()),
+                    );
+                    egui::CollapsingHeader::new(header).id_source(group.id).show(ui, |ui| {
+                        for (idx, member) in group.members.iter().enumerate() {
+                            let path = &member.path;
                             ui.horizontal(|ui| {
+                                let mut checked = self.selection.contains(path);
+                                let selectable = idx != group.keeper && !member.reference;
+                                if ui.add_enabled(selectable, egui::Checkbox::new(&mut checked, "")).changed() {
+                                    self.selection.set(path, checked);
+                                }
+                                if ui.selectable_label(idx == group.keeper, "⭐")
+                                    .on_hover_text("Keep this copy")
+                                    .clicked() {
+                                    new_keeper = Some((group_idx, idx));
+                                }
+                                if self.result.roots.len() > 1 {
+                                    if let Some(root) = self.result.roots.get(member.root) {
+                                        ui.label(format!("[{}]", member.root + 1))
+                                            .on_hover_text(root.path.display().to_string());
+                                    }
+                                }
+                                if member.reference {
+                                    ui.label("🔒").on_hover_text("Reference file, never modified");
+                                }
+                                if let Some(links) = self.result.hardlinks.get(path) {
+                                    let others: Vec<String> = links.iter().map(|link| link.display().to_string()).collect();
+                                    ui.label(format!("🔗 +{}", links.len()))
+                                        .on_hover_text(format!("Already hard linked as:\n{}", others.join("\n")));
+                                }
                                 if ui.selectable_label(
                                     self.selected_file.as_ref() == Some(path),
                                     path.display().to_string()
@@ -122,24 +953,37 @@ impl DuplicateFinderApp {
                                     }
                                 }
 
-                                if path != original {
+                                if idx != group.keeper && !member.reference {
                                     if ui.button("🗑️ Delete").clicked() {
-                                        if let Err(e) = std::fs::remove_file(path) {
-                                            self.error = Some(e.to_string());
-                                        }
+                                        single.push(path, original, Operation::Delete, group.size, &group.hash);
+                                    }
+                                    if ui.button("♻ Trash").clicked() {
+                                        single.push(path, original, Operation::Trash, group.size, &group.hash);
                                     }
                                     if ui.button("🔗 Hardlink").clicked() {
-                                        if let Err(e) = create_hardlink(original, path) {
-                                            self.error = Some(e.to_string());
-                                        }
+                                        let operation = Operation::Hardlink {
+                                            keeper: original.clone(),
+                                            preserve_permissions: self.batch_options.preserve_permissions
+                                                && !group.members[group.keeper].reference,
+                                        };
+                                        single.push(path, original, operation, group.size, &group.hash);
+                                    }
+                                    if ui.button("🧬 Reflink").on_hover_text("Share storage but keep an independent copy").clicked() {
+                                        let operation = Operation::Reflink { keeper: original.clone() };
+                                        single.push(path, original, operation, group.size, &group.hash);
+                                    }
+                                    if ui.button("↪ Symlink").clicked() {
+                                        let operation = Operation::Symlink {
+                                            keeper: original.clone(),
+                                            relative: self.batch_options.relative_symlinks,
+                                        };
+                                        single.push(path, original, operation, group.size, &group.hash);
                                     }
                                     if ui.button("📦 Move").clicked() {
                                         if let Some(dst) = FileDialog::new()
                                             .set_file_name(path.file_name().unwrap().to_str().unwrap())
                                             .save_file() {
-                                            if let Err(e) = move_file(path, &dst) {
-                                                self.error = Some(e.to_string());
-                                            }
+                                            single.push(path, original, Operation::Move { destination: dst }, group.size, &group.hash);
                                         }
                                     }
                                 }
@@ -149,6 +993,15 @@ impl DuplicateFinderApp {
                 }
             }
         });
+
+        if let Some((group_idx, idx)) = new_keeper {
+            let group = &mut self.result.duplicates[group_idx];
+            group.keeper = idx;
+            self.keeper_overrides.insert(group.id, group.members[idx].path.clone());
+        }
+        if !single.steps.is_empty() {
+            self.run_or_queue(single);
+        }
     }
 
     fn show_preview_section(&mut self, ui: &mut Ui) {
@@ -173,45 +1026,144 @@ impl DuplicateFinderApp {
     }
 
     fn start_scan(&mut self) {
+        let filter = match FileFilter::new(&self.config.filters) {
+            Ok(filter) => filter,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return;
+            }
+        };
         self.scanning = true;
-        self.progress = 0.0;
-        self.duplicates.clear();
+        self.progress = ScanProgress::default();
+        self.result = ScanResult::default();
+        self.selection.clear();
         self.error = None;
         self.selected_file = None;
         self.preview = None;
+
+        // Remember the settings used for the last scan
+        if let Err(e) = self.config.save() {
+            self.error = Some(format!("Could not save settings: {}", e));
+        }
+        if let Some(journal) = &self.journal {
+            self.scanner.set_dedup_links(journal.symlinks());
+        }
         
-        self.scanner.start_scan(
-            Path::new(&self.directory),
-            self.filters.clone(),
-        );
+        self.scan_handle = Some(self.scanner.start_scan(
+            &self.enabled_roots(),
+            filter,
+            self.config.hash.clone(),
+        ));
+        self.config.hash.force_rehash = false;
     }
 }
 
 impl eframe::App for DuplicateFinderApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.handle_scanner_messages();
+        self.handle_execution();
 
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("DupFi - Duplicate File Finder");
 
+            if let Some(execution) = &self.execution {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(format!("Executing {} steps…", execution.steps));
+                });
+            }
+
             if let Some(error) = &self.error {
                 ui.colored_label(egui::Color32::RED, error);
             }
 
             self.show_directory_section(ui);
             self.show_filters_section(ui);
+            self.show_hashing_section(ui);
             
+            if !self.result.stages.is_empty() {
+                self.show_statistics_section(ui);
+            }
+
+            if !self.result.problems.is_empty() {
+                self.show_problems_section(ui);
+            }
+
+            if !self.result.collisions.is_empty() {
+                self.show_collisions_section(ui);
+            }
+
+            if !self.result.hardlinks.is_empty() {
+                self.show_hardlinks_section(ui);
+            }
+
+            if !self.result.symlinks.is_empty() {
+                self.show_symlinks_section(ui);
+            }
+
+            if !self.result.deduplicated.is_empty() {
+                self.show_deduplicated_section(ui);
+            }
+
+            if self.journal.as_ref().is_some_and(|journal| !journal.batches().is_empty()) {
+                self.show_history_section(ui);
+            }
+
             ui.separator();
 
-            if !self.duplicates.is_empty() {
+            if !self.result.duplicates.is_empty() {
                 self.show_duplicates_section(ui);
                 self.show_preview_section(ui);
             }
         });
 
+        self.show_plan_window(ctx);
+
         // Request repaint while scanning to update progress
         if self.scanning {
             ctx.request_repaint();
+        } else if self.execution.is_some() {
+            ctx.request_repaint_after(Duration::from_millis(100));
         }
     }
 }
+
+fn optional_size(ui: &mut Ui, label: &str, value: &mut Option<u64>) {
+    ui.horizontal(|ui| {
+        let mut enabled = value.is_some();
+        if ui.checkbox(&mut enabled, label).changed() {
+            *value = enabled.then_some(0);
+        }
+        if let Some(bytes) = value {
+            let mut kib = *bytes / 1024;
+            if ui.add(egui::DragValue::new(&mut kib)).changed() {
+                *bytes = kib * 1024;
+            }
+        }
+    });
+}
+
+fn optional_id(ui: &mut Ui, label: &str, value: &mut Option<u32>) {
+    ui.horizontal(|ui| {
+        let mut enabled = value.is_some();
+        if ui.checkbox(&mut enabled, label).changed() {
+            *value = enabled.then_some(0);
+        }
+        if let Some(id) = value {
+            ui.add(egui::DragValue::new(id));
+        }
+    });
+}
+
+fn date_edit(ui: &mut Ui, label: &str, text: &mut String, value: &mut Option<NaiveDate>) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        let response = ui.add(egui::TextEdit::singleline(text).hint_text("YYYY-MM-DD").desired_width(100.0));
+        if response.changed() {
+            *value = NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d").ok();
+        }
+        if !text.trim().is_empty() && value.is_none() {
+            ui.colored_label(egui::Color32::RED, "invalid date");
+        }
+    });
+}
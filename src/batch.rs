@@ -0,0 +1,295 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+use crate::{
+    file_utils::{DuplicateGroup, DuplicateMember, ScanRoot},
+    plan::{ActionPlan, Operation},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchAction {
+    Delete,
+    Trash,
+    Hardlink,
+    Reflink,
+    Symlink,
+    /// Move into a folder, keeping the path relative to the scan root.
+    Move,
+}
+
+impl BatchAction {
+    pub const ALL: [BatchAction; 6] = [
+        BatchAction::Delete,
+        BatchAction::Trash,
+        BatchAction::Hardlink,
+        BatchAction::Reflink,
+        BatchAction::Symlink,
+        BatchAction::Move,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BatchAction::Delete => "🗑️ Delete",
+            BatchAction::Trash => "♻ Move to trash",
+            BatchAction::Hardlink => "🔗 Hardlink",
+            BatchAction::Reflink => "🧬 Reflink",
+            BatchAction::Symlink => "↪ Symlink",
+            BatchAction::Move => "📦 Move to folder",
+        }
+    }
+}
+
+/// Duplicates checked for a batch action. Keepers and reference files are
+/// never acted on, even when checked.
+#[derive(Debug, Default)]
+pub struct Selection {
+    paths: HashSet<PathBuf>,
+}
+
+/// A selected duplicate and the copy it is replaced by.
+pub struct BatchTarget<'a> {
+    pub keeper: &'a Path,
+    pub keeper_reference: bool,
+    pub member: &'a DuplicateMember,
+    pub root: Option<&'a Path>,
+    pub size: u64,
+    pub hash: &'a [u8],
+}
+
+impl Selection {
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths.contains(path)
+    }
+
+    pub fn set(&mut self, path: &Path, selected: bool) {
+        if selected {
+            self.paths.insert(path.to_path_buf());
+        } else {
+            self.paths.remove(path);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.paths.clear();
+    }
+
+    /// Adds every member other than the keeper that matches `predicate`.
+    pub fn select_where(&mut self, groups: &[DuplicateGroup], mut predicate: impl FnMut(&DuplicateMember) -> bool) {
+        for group in groups {
+            for (idx, member) in group.members.iter().enumerate() {
+                if idx != group.keeper && !member.reference && predicate(member) {
+                    self.paths.insert(member.path.clone());
+                }
+            }
+        }
+    }
+
+    pub fn targets<'a>(&self, groups: &'a [DuplicateGroup], roots: &'a [ScanRoot]) -> Vec<BatchTarget<'a>> {
+        groups.iter()
+            .flat_map(|group| {
+                let keeper = &group.members[group.keeper].path;
+                group.members.iter()
+                    .enumerate()
+                    .filter(move |(idx, member)| *idx != group.keeper && !member.reference)
+                    .filter(|(_, member)| self.paths.contains(&member.path))
+                    .map(move |(_, member)| BatchTarget {
+                        keeper,
+                        keeper_reference: group.members[group.keeper].reference,
+                        member,
+                        root: roots.get(member.root).map(|root| root.path.as_path()),
+                        size: group.size,
+                        hash: &group.hash,
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Settings shared by the steps of a batch.
+#[derive(Debug, Default)]
+pub struct BatchOptions {
+    /// Required for [`BatchAction::Move`].
+    pub destination: Option<PathBuf>,
+    pub preserve_permissions: bool,
+    /// Write symbolic links relative to their own directory.
+    pub relative_symlinks: bool,
+}
+
+/// Plans `action` for every target without touching any file.
+pub fn plan(action: BatchAction, targets: &[BatchTarget], options: &BatchOptions) -> io::Result<ActionPlan> {
+    let mut plan = ActionPlan::default();
+    let mut destinations = HashSet::new();
+    for target in targets {
+        let path = &target.member.path;
+        let operation = match action {
+            BatchAction::Delete => Operation::Delete,
+            BatchAction::Trash => Operation::Trash,
+            BatchAction::Hardlink => Operation::Hardlink {
+                keeper: target.keeper.to_path_buf(),
+                // The shared inode is the keeper's, reference files stay untouched
+                preserve_permissions: options.preserve_permissions && !target.keeper_reference,
+            },
+            BatchAction::Reflink => Operation::Reflink { keeper: target.keeper.to_path_buf() },
+            BatchAction::Symlink => Operation::Symlink {
+                keeper: target.keeper.to_path_buf(),
+                relative: options.relative_symlinks,
+            },
+            BatchAction::Move => {
+                let destination = options.destination.as_deref()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no destination folder chosen"))?;
+                let destination = unique_destination(move_destination(destination, target.root, path), &destinations);
+                destinations.insert(destination.clone());
+                Operation::Move { destination }
+            }
+        };
+        plan.push(path, target.keeper, operation, target.size, target.hash);
+    }
+    Ok(plan)
+}
+
+/// Where a moved file lands: below `destination`, under the name of its
+/// scan root, so equally named duplicates from different places don't clash.
+fn move_destination(destination: &Path, root: Option<&Path>, path: &Path) -> PathBuf {
+    match root.and_then(|root| Some((root.file_name()?, path.strip_prefix(root).ok()?))) {
+        Some((root_name, relative)) => destination.join(root_name).join(relative),
+        None => destination.join(path.file_name().unwrap_or_default()),
+    }
+}
+
+/// Numbers `path` like "name (2).ext" if it is taken, on disk or by an
+/// earlier step, as happens for equally named roots.
+fn unique_destination(path: PathBuf, taken: &HashSet<PathBuf>) -> PathBuf {
+    let is_free = |path: &Path| !taken.contains(path) && fs::symlink_metadata(path).is_err();
+    if is_free(&path) {
+        return path;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| is_free(candidate))
+        .unwrap_or(path)
+}
+
+/// Drops the given paths from the groups, and groups left with one member.
+pub fn forget(groups: &mut Vec<DuplicateGroup>, paths: &[PathBuf]) {
+    let paths: HashSet<&PathBuf> = paths.iter().collect();
+    for group in groups.iter_mut() {
+        let keeper = group.members[group.keeper].path.clone();
+        group.members.retain(|member| !paths.contains(&member.path));
+        group.keeper = group.members.iter().position(|member| member.path == keeper).unwrap_or(0);
+    }
+    groups.retain(|group| group.members.len() > 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_utils::move_file;
+    use tempfile::tempdir;
+
+    fn group(dir: &Path, names: &[&str], reference: &[bool]) -> DuplicateGroup {
+        DuplicateGroup {
+            id: 0,
+            size: 4,
+            hash: Vec::new(),
+            keeper: 0,
+            members: names.iter()
+                .zip(reference)
+                .map(|(name, &reference)| {
+                    let path = dir.join(name);
+                    fs::write(&path, b"same").unwrap();
+                    DuplicateMember { path, modified: None, root: 0, reference }
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_select_all_except_keeper() {
+        let temp_dir = tempdir().unwrap();
+        let groups = vec![group(temp_dir.path(), &["a", "b", "c"], &[false, false, true])];
+        let mut selection = Selection::default();
+        selection.select_where(&groups, |_| true);
+
+        assert!(!selection.contains(&groups[0].members[0].path));
+        assert!(selection.contains(&groups[0].members[1].path));
+        assert!(!selection.contains(&groups[0].members[2].path));
+
+        // Checking the keeper by hand still leaves it alone
+        selection.set(&groups[0].members[0].path, true);
+        assert_eq!(selection.targets(&groups, &[]).len(), 1);
+    }
+
+    #[test]
+    fn test_plan_and_forget() {
+        let temp_dir = tempdir().unwrap();
+        let mut groups = vec![group(temp_dir.path(), &["a", "b", "c"], &[false, false, false])];
+        let mut selection = Selection::default();
+        selection.set(&groups[0].members[1].path, true);
+
+        let plan = plan(BatchAction::Symlink, &selection.targets(&groups, &[]), &BatchOptions::default()).unwrap();
+        assert_eq!(plan.steps.len(), 1);
+        assert_eq!(plan.steps[0].path, groups[0].members[1].path);
+        assert_eq!(plan.steps[0].operation, Operation::Symlink { keeper: groups[0].members[0].path.clone(), relative: false });
+        assert_eq!(plan.reclaimed(), 4);
+
+        // A reference keeper never takes on the duplicate's permissions
+        let referenced = vec![group(temp_dir.path(), &["d", "e"], &[true, false])];
+        let mut both = Selection::default();
+        both.select_where(&referenced, |_| true);
+        let options = BatchOptions { preserve_permissions: true, ..Default::default() };
+        let linked = super::plan(BatchAction::Hardlink, &both.targets(&referenced, &[]), &options).unwrap();
+        assert!(matches!(linked.steps[0].operation, Operation::Hardlink { preserve_permissions: false, .. }));
+
+        forget(&mut groups, &[plan.steps[0].path.clone()]);
+        assert_eq!(groups[0].members.len(), 2);
+        forget(&mut groups, &[temp_dir.path().join("c")]);
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn test_move_requires_destination() {
+        let temp_dir = tempdir().unwrap();
+        let groups = vec![group(temp_dir.path(), &["a", "b"], &[false, false])];
+        let mut selection = Selection::default();
+        selection.select_where(&groups, |_| true);
+        assert!(plan(BatchAction::Move, &selection.targets(&groups, &[]), &BatchOptions::default()).is_err());
+
+        let destination = move_destination(Path::new("/out"), Some(Path::new("/data/photos")), Path::new("/data/photos/2020/a.jpg"));
+        assert_eq!(destination, Path::new("/out/photos/2020/a.jpg"));
+    }
+
+    #[test]
+    fn test_move_keeps_same_named_roots_apart() {
+        let temp_dir = tempdir().unwrap();
+        let roots: Vec<ScanRoot> = ["a", "b"].iter()
+            .map(|dir| ScanRoot::new(temp_dir.path().join(dir).join("photos"), false))
+            .collect();
+        let mut groups = vec![group(temp_dir.path(), &["keeper"], &[false])];
+        for (idx, root) in roots.iter().enumerate() {
+            fs::create_dir_all(&root.path).unwrap();
+            let path = root.path.join("x.jpg");
+            fs::write(&path, b"same").unwrap();
+            groups[0].members.push(DuplicateMember { path, modified: None, root: idx, reference: false });
+        }
+        let mut selection = Selection::default();
+        selection.select_where(&groups, |_| true);
+
+        let out = temp_dir.path().join("out");
+        let options = BatchOptions { destination: Some(out.clone()), ..Default::default() };
+        let plan = plan(BatchAction::Move, &selection.targets(&groups, &roots), &options).unwrap();
+        for step in &plan.steps {
+            step.run(&temp_dir.path().join("unused")).unwrap();
+        }
+        assert_eq!(fs::read(out.join("photos").join("x.jpg")).unwrap(), b"same");
+        assert_eq!(fs::read(out.join("photos").join("x (2).jpg")).unwrap(), b"same");
+
+        // Moving never replaces a file that is already there
+        let err = move_file(&groups[0].members[0].path, &out.join("photos").join("x.jpg")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+}
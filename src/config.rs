@@ -0,0 +1,82 @@
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+use serde::{Deserialize, Serialize};
+use crate::{file_utils::HashOptions, filters::FilterOptions, keep::KeepPolicy};
+
+/// Settings persisted between sessions in the user's config directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub hash: HashOptions,
+    pub filters: FilterOptions,
+    pub keep: KeepPolicy,
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("dupfi").join("config.json"))
+    }
+
+    /// Loads the config from the default location, falling back to defaults
+    /// if it is missing or unreadable.
+    pub fn load() -> Self {
+        Self::default_path()
+            .and_then(|path| Self::load_from(&path).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn load_from(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        match Self::default_path() {
+            Some(path) => self.save_to(&path),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no config directory available")),
+        }
+    }
+
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filters::{FilterRule, RuleKind}, hasher::HashAlgorithm};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_config_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("nested").join("config.json");
+
+        let mut config = Config::default();
+        config.hash.algorithm = HashAlgorithm::Xxh3;
+        config.hash.chunk_size = 4096;
+        config.filters.rules.push(FilterRule::exclude(RuleKind::Directory, "target"));
+        config.save_to(&path).unwrap();
+
+        assert_eq!(Config::load_from(&path).unwrap(), config);
+    }
+
+    #[test]
+    fn test_partial_config_uses_defaults() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("config.json");
+        fs::write(&path, r#"{ "hash": { "algorithm": "Blake3" } }"#).unwrap();
+
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.hash.algorithm, HashAlgorithm::Blake3);
+        assert_eq!(config.hash.chunk_size, HashOptions::default().chunk_size);
+    }
+}
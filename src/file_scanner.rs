@@ -1,23 +1,36 @@
 use std::{
-    path::{Path, PathBuf},
-    sync::{Arc, atomic::{AtomicUsize, Ordering}},
     collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+    time::Duration,
 };
 use crossbeam_channel::{bounded, Sender, Receiver};
 use notify::{Watcher, RecursiveMode, Event};
-use crate::file_utils::{collect_files, find_duplicates};
+use crate::{
+    file_utils::{collect_roots, find_duplicates, HashOptions, ScanContext, ScanResult, ScanRoot},
+    filters::FileFilter,
+    hash_cache::HashCache,
+    problems::ScanProblem,
+    scan_handle::{ScanHandle, ScanPhase, ScanProgress},
+};
 
 pub enum ScannerMessage {
-    Progress(f32),
-    Found(HashMap<Vec<u8>, Vec<PathBuf>>),
+    Progress(ScanProgress),
+    /// The watched directory changed and results may be stale.
+    Changed,
+    /// A file or directory had to be skipped. All problems are also part of
+    /// the final result.
+    Problem(ScanProblem),
+    Found(Box<ScanResult>),
     Error(String),
 }
 
 pub struct Scanner {
     tx: Sender<ScannerMessage>,
     rx: Receiver<ScannerMessage>,
-    total_files: Arc<AtomicUsize>,
-    processed_files: Arc<AtomicUsize>,
+    cache: Option<Arc<HashCache>>,
+    dedup_links: Arc<HashMap<PathBuf, PathBuf>>,
+    active: Mutex<Option<ScanHandle>>,
 }
 
 impl Scanner {
@@ -26,60 +39,92 @@ impl Scanner {
         Self {
             tx,
             rx,
-            total_files: Arc::new(AtomicUsize::new(0)),
-            processed_files: Arc::new(AtomicUsize::new(0)),
+            cache: None,
+            dedup_links: Arc::default(),
+            active: Mutex::new(None),
         }
     }
 
+    pub fn set_cache(&mut self, cache: Option<Arc<HashCache>>) {
+        self.cache = cache;
+    }
+
+    /// Symbolic links made by earlier replacements, with their keepers.
+    /// Scans report these as already deduplicated.
+    pub fn set_dedup_links(&mut self, links: HashMap<PathBuf, PathBuf>) {
+        self.dedup_links = Arc::new(links);
+    }
+
     pub fn receiver(&self) -> Receiver<ScannerMessage> {
         self.rx.clone()
     }
 
-    pub fn start_scan(&self, path: &Path, filters: Vec<String>) {
+    /// Cancels the running scan, if any. A cancelled scan sends no further
+    /// messages.
+    pub fn cancel(&self) {
+        if let Some(handle) = self.active.lock().unwrap().take() {
+            handle.cancel();
+        }
+    }
+
+    /// Starts a new scan, cancelling any scan that is still running.
+    pub fn start_scan(&self, roots: &[ScanRoot], filter: FileFilter, options: HashOptions) -> ScanHandle {
+        self.cancel();
+        // Drop anything the previous scan managed to send before it stopped
+        while self.rx.try_recv().is_ok() {}
+
+        let handle = ScanHandle::new();
+        *self.active.lock().unwrap() = Some(handle.clone());
+
         let tx = self.tx.clone();
-        let path = path.to_path_buf();
-        let total_files = self.total_files.clone();
-        let processed_files = self.processed_files.clone();
+        let roots = roots.to_vec();
+        let mut ctx = ScanContext::new(self.cache.clone(), handle.clone());
+        ctx.dedup_links = self.dedup_links.clone();
 
         std::thread::spawn(move || {
-            // Collect all files
-            let mut files = collect_files(&path, &filters);
-            total_files.store(files.len(), Ordering::SeqCst);
-            
-            // Set up progress tracking
+            let finished = Arc::new(AtomicBool::new(false));
+            ctx.handle.start_phase(ScanPhase::Walking, 0, 0);
+            let _ = tx.send(ScannerMessage::Progress(ctx.handle.progress()));
+
+            // Report progress periodically until the scan is done
+            // along with any problems found since the last report
             let progress_tx = tx.clone();
-            let progress_processed = processed_files.clone();
-            let progress_total = total_files.clone();
-            
+            let progress_ctx = ctx.clone();
+            let progress_finished = finished.clone();
             std::thread::spawn(move || {
+                let mut problems_sent = 0;
                 loop {
-                    let processed = progress_processed.load(Ordering::SeqCst);
-                    let total = progress_total.load(Ordering::SeqCst);
-                    
-                    if total == 0 {
+                    std::thread::sleep(Duration::from_millis(100));
+                    if progress_finished.load(Ordering::SeqCst) || progress_ctx.handle.is_cancelled() {
                         return;
                     }
-                    
-                    let progress = processed as f32 / total as f32;
-                    if progress_tx.send(ScannerMessage::Progress(progress)).is_err() {
-                        return;
+                    for problem in progress_ctx.problems_since(problems_sent) {
+                        problems_sent += 1;
+                        if progress_tx.send(ScannerMessage::Problem(problem)).is_err() {
+                            return;
+                        }
                     }
-                    
-                    if processed == total {
+                    if progress_tx.send(ScannerMessage::Progress(progress_ctx.handle.progress())).is_err() {
                         return;
                     }
-                    
-                    std::thread::sleep(std::time::Duration::from_millis(100));
                 }
             });
 
+            // Collect all files
+            let mut files = collect_roots(&roots, &filter, &ctx);
+
             // Find duplicates
-            match find_duplicates(&mut files) {
-                dups => {
-                    let _ = tx.send(ScannerMessage::Found(dups));
-                }
+            let mut result = find_duplicates(&mut files, &options, &ctx);
+            result.roots = roots;
+            result.filters = filter.options().clone();
+            result.options = options;
+            finished.store(true, Ordering::SeqCst);
+            if !ctx.handle.is_cancelled() {
+                let _ = tx.send(ScannerMessage::Found(Box::new(result)));
             }
         });
+
+        handle
     }
 
     pub fn watch_directory(&self, path: &Path) -> notify::Result<impl Watcher> {
@@ -87,7 +132,7 @@ impl Scanner {
         let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
             match res {
                 Ok(_) => {
-                    let _ = tx.send(ScannerMessage::Progress(-1.0)); // Trigger a rescan
+                    let _ = tx.send(ScannerMessage::Changed); // Trigger a rescan
                 }
                 Err(e) => {
                     let _ = tx.send(ScannerMessage::Error(e.to_string()));
@@ -122,13 +167,13 @@ mod tests {
         let scanner = Scanner::new();
         let receiver = scanner.receiver();
         
-        scanner.start_scan(temp_dir.path(), vec![]);
+        scanner.start_scan(&[ScanRoot::new(temp_dir.path(), false)], FileFilter::default(), HashOptions::default());
         
         let mut found_duplicates = false;
         while let Ok(message) = receiver.recv_timeout(std::time::Duration::from_secs(5)) {
             match message {
-                ScannerMessage::Found(duplicates) => {
-                    assert_eq!(duplicates.len(), 1);
+                ScannerMessage::Found(result) => {
+                    assert_eq!(result.duplicates.len(), 1);
                     found_duplicates = true;
                     break;
                 }
@@ -141,4 +186,32 @@ mod tests {
         
         assert!(found_duplicates, "Should have found duplicates");
     }
+
+    #[test]
+    fn test_new_scan_cancels_previous() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("file1.txt"), b"same").unwrap();
+        fs::write(temp_dir.path().join("file2.txt"), b"same").unwrap();
+
+        let scanner = Scanner::new();
+        let receiver = scanner.receiver();
+
+        let first = scanner.start_scan(&[ScanRoot::new(temp_dir.path(), false)], FileFilter::default(), HashOptions::default());
+        let second = scanner.start_scan(&[ScanRoot::new(temp_dir.path(), false)], FileFilter::default(), HashOptions::default());
+        assert!(first.is_cancelled());
+        assert!(!second.is_cancelled());
+
+        let mut found = false;
+        while let Ok(message) = receiver.recv_timeout(std::time::Duration::from_secs(5)) {
+            if let ScannerMessage::Found(result) = message {
+                assert_eq!(result.duplicates.len(), 1);
+                found = true;
+                break;
+            }
+        }
+        assert!(found, "Second scan should report its results");
+
+        scanner.cancel();
+        assert!(second.is_cancelled());
+    }
 }
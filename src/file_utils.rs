@@ -1,103 +1,1236 @@
 use std::{
     fs,
-    io,
-    path::{Path, PathBuf},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Component, Path, PathBuf},
     collections::HashMap,
+    sync::{Arc, Condvar, Mutex, atomic::{AtomicU64, Ordering}},
+    time::{Duration, SystemTime},
 };
-use sha2::{Sha256, Digest};
+use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 use rayon::prelude::*;
+use crate::{
+    filters::{FileFilter, FilterOptions, SymlinkPolicy},
+    hash_cache::{CacheStats, HashCache},
+    hasher::HashAlgorithm,
+    problems::ScanProblem,
+    reflink,
+    scan_handle::{ScanHandle, ScanPhase},
+};
+
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+pub const DEFAULT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+pub const DEFAULT_PARTIAL_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HashOptions {
+    pub algorithm: HashAlgorithm,
+    /// Size of the read buffer used when streaming a file through the hasher.
+    pub chunk_size: usize,
+    /// Upper bound for all read buffers alive at once across the worker pool.
+    pub memory_budget: usize,
+    /// Number of bytes hashed from the start and from the end of a file
+    /// before committing to a full hash.
+    pub partial_size: usize,
+    /// Confirm every group member byte-for-byte against the first one
+    /// instead of trusting hash equality.
+    pub verify: bool,
+    /// Reuse hashes from the persistent cache for unchanged files.
+    pub use_cache: bool,
+    /// Ignore cached hashes for this scan (fresh results are still stored).
+    #[serde(skip)]
+    pub force_rehash: bool,
+}
+
+impl Default for HashOptions {
+    fn default() -> Self {
+        Self {
+            algorithm: HashAlgorithm::default(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            partial_size: DEFAULT_PARTIAL_SIZE,
+            verify: false,
+            use_cache: true,
+            force_rehash: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum HashStage {
+    Size,
+    Head,
+    Tail,
+    Full,
+    Verify,
+}
+
+impl HashStage {
+    pub fn label(&self) -> &'static str {
+        match self {
+            HashStage::Size => "Size",
+            HashStage::Head => "Head hash",
+            HashStage::Tail => "Tail hash",
+            HashStage::Full => "Full hash",
+            HashStage::Verify => "Byte compare",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageStats {
+    pub stage: HashStage,
+    /// Files entering the stage.
+    pub candidates: usize,
+    /// Files still colliding after the stage.
+    pub remaining: usize,
+    pub bytes_read: u64,
+    /// Size of the files the stage eliminated, i.e. full reads avoided.
+    pub bytes_saved: u64,
+}
+
+/// Files that share a hash but turned out to differ when compared byte by
+/// byte, split into groups of identical content.
+#[derive(Debug, Clone)]
+pub struct HashCollision {
+    pub hash: Vec<u8>,
+    pub groups: Vec<Vec<PathBuf>>,
+}
+
+/// A directory to scan. Files under a reference root take part in matching
+/// but are never offered for removal.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanRoot {
+    pub path: PathBuf,
+    pub reference: bool,
+}
+
+impl ScanRoot {
+    pub fn new(path: impl Into<PathBuf>, reference: bool) -> Self {
+        Self { path: path.into(), reference }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateMember {
+    pub path: PathBuf,
+    pub modified: Option<SystemTime>,
+    /// Index into the scan's roots.
+    pub root: usize,
+    pub reference: bool,
+}
+
+/// A set of files with identical content, members ordered by path.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateGroup {
+    /// Derived from the size and content hash, so the same group keeps its
+    /// ID across scans with the same algorithm.
+    pub id: u64,
+    pub size: u64,
+    pub hash: Vec<u8>,
+    pub members: Vec<DuplicateMember>,
+    /// Index of the member to keep, see [`crate::keep::KeepPolicy`].
+    pub keeper: usize,
+}
+
+impl DuplicateGroup {
+    fn new(files: &[FileInfo], group: &[usize]) -> Option<Self> {
+        let first = &files[*group.first()?];
+        let hash = first.hash.clone()?;
+        let mut members: Vec<DuplicateMember> = group.iter()
+            .map(|&idx| {
+                let file = &files[idx];
+                DuplicateMember {
+                    path: file.path.clone(),
+                    modified: file.modified,
+                    root: file.root,
+                    reference: file.reference,
+                }
+            })
+            .collect();
+        members.sort_by(|a, b| a.path.cmp(&b.path));
+        let keeper = members.iter().position(|member| member.reference).unwrap_or(0);
+
+        Some(Self {
+            id: Self::id_for(first.size, &hash, 0),
+            size: first.size,
+            hash,
+            members,
+            keeper,
+        })
+    }
+
+    /// Derived from size and hash so it survives rescans. Classes that
+    /// verification split off one hash are numbered to keep their IDs apart.
+    fn id_for(size: u64, hash: &[u8], class: u64) -> u64 {
+        let mut key = size.to_le_bytes().to_vec();
+        key.extend_from_slice(hash);
+        if class > 0 {
+            key.extend_from_slice(&class.to_le_bytes());
+        }
+        xxhash_rust::xxh3::xxh3_64(&key)
+    }
+
+    /// Bytes that would be freed by keeping a single copy.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.members.len() as u64).saturating_sub(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GroupSort {
+    #[default]
+    WastedSpace,
+    MemberCount,
+    Path,
+    Size,
+}
+
+impl GroupSort {
+    pub const ALL: [GroupSort; 4] = [
+        GroupSort::WastedSpace,
+        GroupSort::MemberCount,
+        GroupSort::Path,
+        GroupSort::Size,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GroupSort::WastedSpace => "Wasted space",
+            GroupSort::MemberCount => "Member count",
+            GroupSort::Path => "Path",
+            GroupSort::Size => "File size",
+        }
+    }
+}
+
+/// Orders groups largest first, or by path; ties fall back to the path and
+/// then the ID, so the order never depends on hashing or thread timing.
+pub fn sort_groups(groups: &mut [DuplicateGroup], order: GroupSort) {
+    let first_path = |group: &DuplicateGroup| group.members.first().map(|member| member.path.clone());
+    groups.sort_by(|a, b| {
+        let primary = match order {
+            GroupSort::WastedSpace => b.wasted().cmp(&a.wasted()),
+            GroupSort::MemberCount => b.members.len().cmp(&a.members.len()),
+            GroupSort::Size => b.size.cmp(&a.size),
+            GroupSort::Path => std::cmp::Ordering::Equal,
+        };
+        primary
+            .then_with(|| first_path(a).cmp(&first_path(b)))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+}
+
+#[derive(Debug, Default)]
+pub struct ScanResult {
+    /// Duplicate groups, in the default [`GroupSort`] order.
+    pub duplicates: Vec<DuplicateGroup>,
+    pub collisions: Vec<HashCollision>,
+    pub stages: Vec<StageStats>,
+    pub cache_stats: CacheStats,
+    /// The scanned root directories.
+    pub roots: Vec<ScanRoot>,
+    /// Existing hard link clusters: the other scanned paths of each file,
+    /// keyed by the path it is reported under.
+    pub hardlinks: HashMap<PathBuf, Vec<PathBuf>>,
+    /// Symbolic links to scanned files and the path each resolves to. They
+    /// are never reported as duplicates of their targets.
+    pub symlinks: HashMap<PathBuf, PathBuf>,
+    /// Symbolic links an earlier symlink replacement created, and the kept
+    /// copy each still points to. They count as already deduplicated.
+    pub deduplicated: HashMap<PathBuf, PathBuf>,
+    /// Per-device breakdown, ordered by device number.
+    pub devices: Vec<DeviceSummary>,
+    /// Everything that could not be read, in the order it happened.
+    pub problems: Vec<ScanProblem>,
+    /// Filter settings the scan ran with.
+    pub filters: FilterOptions,
+    /// Hash settings the scan ran with.
+    pub options: HashOptions,
+}
+
+/// How the scanned files and duplicates are spread over one device.
+/// Hard links can only reclaim space between copies on the same device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DeviceSummary {
+    pub device: u64,
+    /// A scanned file on the device, to tell devices apart in the UI.
+    pub example: PathBuf,
+    pub files: usize,
+    pub duplicate_files: usize,
+    pub groups: usize,
+    /// Bytes freed by hard linking the copies on this device together.
+    pub reclaimable: u64,
+    /// The file system can share extents between copies, see [`crate::reflink`].
+    pub reflink: bool,
+}
+
+/// Runtime services a scan can use beyond its options.
+#[derive(Clone, Default)]
+pub struct ScanContext {
+    pub cache: Option<Arc<HashCache>>,
+    pub handle: ScanHandle,
+    symlinks: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
+    /// Links created by replacing duplicates, with the keeper each points to.
+    pub dedup_links: Arc<HashMap<PathBuf, PathBuf>>,
+    deduplicated: Arc<Mutex<HashMap<PathBuf, PathBuf>>>,
+    problems: Arc<Mutex<Vec<ScanProblem>>>,
+}
+
+impl ScanContext {
+    pub fn new(cache: Option<Arc<HashCache>>, handle: ScanHandle) -> Self {
+        Self { cache, handle, ..Default::default() }
+    }
+
+    fn record_symlink(&self, link: PathBuf, target: PathBuf) {
+        self.symlinks.lock().unwrap().insert(link, target);
+    }
+
+    fn take_symlinks(&self) -> HashMap<PathBuf, PathBuf> {
+        std::mem::take(&mut *self.symlinks.lock().unwrap())
+    }
+
+    /// Whether `path` is a link from an earlier symlink replacement that
+    /// still resolves to its keeper. Such links are recorded, not scanned.
+    fn record_dedup_link(&self, path: &Path) -> bool {
+        let Some(keeper) = self.dedup_links.get(path) else {
+            return false;
+        };
+        let resolves = fs::canonicalize(path).is_ok_and(|target| fs::canonicalize(keeper).is_ok_and(|keeper| keeper == target));
+        if resolves {
+            self.deduplicated.lock().unwrap().insert(path.to_path_buf(), keeper.clone());
+        }
+        resolves
+    }
+
+    pub fn report(&self, problem: ScanProblem) {
+        self.problems.lock().unwrap().push(problem);
+    }
+
+    /// Reports a failed operation on `path`, unless it failed only because
+    /// the scan was cancelled.
+    fn report_io(&self, path: &Path, phase: ScanPhase, err: &io::Error) {
+        if err.kind() != io::ErrorKind::Interrupted {
+            self.report(ScanProblem::io(Some(path), phase, err));
+        }
+    }
+
+    /// Problems reported so far, skipping the first `start`.
+    pub fn problems_since(&self, start: usize) -> Vec<ScanProblem> {
+        self.problems.lock().unwrap().iter().skip(start).cloned().collect()
+    }
+}
+
+/// Counting semaphore over bytes, shared by the hashing workers so that
+/// peak buffer memory stays bounded no matter how many threads rayon uses.
+pub struct MemoryBudget {
+    capacity: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+pub struct BudgetPermit<'a> {
+    budget: &'a MemoryBudget,
+    bytes: usize,
+}
+
+impl MemoryBudget {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            available: Mutex::new(capacity),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until `bytes` (capped at the total capacity) are free.
+    pub fn acquire(&self, bytes: usize) -> BudgetPermit<'_> {
+        let bytes = bytes.clamp(1, self.capacity);
+        let mut available = self.available.lock().unwrap();
+        while *available < bytes {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= bytes;
+        BudgetPermit { budget: self, bytes }
+    }
+}
+
+impl BudgetPermit<'_> {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Drop for BudgetPermit<'_> {
+    fn drop(&mut self) {
+        *self.budget.available.lock().unwrap() += self.bytes;
+        self.budget.released.notify_all();
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct FileInfo {
     pub path: PathBuf,
     pub size: u64,
+    pub modified: Option<SystemTime>,
+    pub device: u64,
+    pub inode: u64,
+    /// Number of hard links to the file, including this one.
+    pub nlink: u64,
+    /// Other scanned paths that are hard links to this same file.
+    pub links: Vec<PathBuf>,
+    /// Whether `path` itself is a symbolic link, which only happens when
+    /// links are followed.
+    pub symlink: bool,
+    /// Index of the scan root the file was found under.
+    pub root: usize,
+    /// Whether the file is protected: its root, or the root of any other
+    /// path folded into it as a link, is a reference root.
+    pub reference: bool,
+    pub head_hash: Option<Vec<u8>>,
+    pub tail_hash: Option<Vec<u8>>,
     pub hash: Option<Vec<u8>>,
 }
 
 impl FileInfo {
     pub fn new(path: PathBuf) -> io::Result<Self> {
         let metadata = fs::metadata(&path)?;
-        Ok(Self {
+        Ok(Self::from_metadata(path, &metadata))
+    }
+
+    pub fn from_metadata(path: PathBuf, metadata: &fs::Metadata) -> Self {
+        let (device, inode, nlink) = file_id(metadata);
+        Self {
             path,
             size: metadata.len(),
+            modified: metadata.modified().ok(),
+            device,
+            inode,
+            nlink,
+            links: Vec::new(),
+            symlink: false,
+            root: 0,
+            reference: false,
+            head_hash: None,
+            tail_hash: None,
             hash: None,
-        })
+        }
     }
 
-    pub fn calculate_hash(&mut self) -> io::Result<()> {
-        let contents = fs::read(&self.path)?;
-        let hash = Sha256::digest(&contents);
-        self.hash = Some(hash.to_vec());
+    /// Streams the file through `buffer`, so memory use is bounded by the
+    /// buffer length rather than the file size.
+    pub fn calculate_hash(&mut self, algorithm: HashAlgorithm, handle: &ScanHandle, buffer: &mut [u8]) -> io::Result<()> {
+        let file = fs::File::open(&self.path)?;
+        self.hash = Some(hash_reader(file, algorithm, handle, buffer)?);
         Ok(())
     }
+
+    /// Computes the key for `stage` unless it is already known and returns
+    /// the number of bytes read. A head hash that covers the whole file is
+    /// reused as tail and full hash.
+    pub fn calculate_stage_hash(&mut self, stage: HashStage, options: &HashOptions, handle: &ScanHandle, buffer: &mut [u8]) -> io::Result<u64> {
+        let partial_len = self.size.min(options.partial_size as u64);
+        match stage {
+            HashStage::Size => Ok(0),
+            HashStage::Head if self.head_hash.is_none() => {
+                let hash = hash_range(&self.path, 0, partial_len, options.algorithm, handle, buffer)?;
+                if partial_len == self.size {
+                    self.tail_hash = Some(hash.clone());
+                    self.hash = Some(hash.clone());
+                }
+                self.head_hash = Some(hash);
+                Ok(partial_len)
+            }
+            HashStage::Tail if self.tail_hash.is_none() => {
+                let offset = self.size - partial_len;
+                self.tail_hash = Some(hash_range(&self.path, offset, partial_len, options.algorithm, handle, buffer)?);
+                Ok(partial_len)
+            }
+            HashStage::Full if self.hash.is_none() => {
+                self.calculate_hash(options.algorithm, handle, buffer)?;
+                Ok(self.size)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// Bytes `calculate_stage_hash` still has to read for `stage`.
+    fn pending_read(&self, stage: HashStage, options: &HashOptions) -> u64 {
+        let partial_len = self.size.min(options.partial_size as u64);
+        match stage {
+            HashStage::Head if self.head_hash.is_none() => partial_len,
+            HashStage::Tail if self.tail_hash.is_none() => partial_len,
+            HashStage::Full if self.hash.is_none() => self.size,
+            _ => 0,
+        }
+    }
+
+    fn stage_key(&self, stage: HashStage) -> Option<Vec<u8>> {
+        match stage {
+            HashStage::Size => Some(self.size.to_le_bytes().to_vec()),
+            HashStage::Head => self.head_hash.clone(),
+            HashStage::Tail => self.tail_hash.clone(),
+            HashStage::Full | HashStage::Verify => self.hash.clone(),
+        }
+    }
+}
+
+/// Device, inode and link count. Zero inodes mean links can't be detected.
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> (u64, u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino(), metadata.nlink())
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> (u64, u64, u64) {
+    (0, 0, 1)
+}
+
+fn hash_reader(mut reader: impl Read, algorithm: HashAlgorithm, handle: &ScanHandle, buffer: &mut [u8]) -> io::Result<Vec<u8>> {
+    let mut hasher = algorithm.hasher();
+    loop {
+        handle.check()?;
+        match reader.read(buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buffer[..n]);
+                handle.add_bytes(n as u64);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+fn hash_range(path: &Path, offset: u64, len: u64, algorithm: HashAlgorithm, handle: &ScanHandle, buffer: &mut [u8]) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    hash_reader(file.take(len), algorithm, handle, buffer)
+}
+
+fn buffer_len(file_size: u64, chunk_size: usize) -> usize {
+    usize::try_from(file_size)
+        .unwrap_or(usize::MAX)
+        .min(chunk_size)
+        .max(1)
 }
 
-pub fn collect_files(dir: &Path, filters: &[String]) -> Vec<FileInfo> {
+pub fn collect_files(dir: &Path, filter: &FileFilter, ctx: &ScanContext) -> Vec<FileInfo> {
+    let policy = filter.options().symlinks;
+    let mut ignores = filter.ignore_stack(dir);
     WalkDir::new(dir)
+        .follow_links(policy == SymlinkPolicy::Follow)
+        .same_file_system(filter.options().same_file_system)
         .into_iter()
-        .filter_map(|e| e.ok())
+        .filter_entry(move |e| {
+            let is_dir = e.file_type().is_dir();
+            if e.depth() > 0 && is_dir && !filter.enters_dir(e.path()) {
+                return false;
+            }
+            !ignores.is_ignored(e.path(), e.depth(), is_dir)
+        })
+        .take_while(|_| ctx.handle.checkpoint())
+        .filter_map(|e| e.map_err(|err| ctx.report(ScanProblem::walk(&err))).ok())
         .filter(|e| {
+            if e.path_is_symlink() && ctx.record_dedup_link(e.path()) {
+                return false;
+            }
+            if policy == SymlinkPolicy::Report && e.path_is_symlink() && filter.accepts(e.path(), dir) {
+                match fs::canonicalize(e.path()) {
+                    Ok(target) if target.is_file() => ctx.record_symlink(e.path().to_path_buf(), target),
+                    Ok(_) => {}
+                    Err(err) => ctx.report_io(e.path(), ScanPhase::Walking, &err),
+                }
+            }
             e.file_type().is_file() && 
-            !should_ignore(&e.path(), filters)
+            filter.accepts(e.path(), dir)
         })
-        .filter_map(|e| FileInfo::new(e.path().to_path_buf()).ok())
+        .filter_map(|e| {
+            // Constraints are checked on the walk's own metadata, so rejected
+            // files cost no extra system call
+            let metadata = e.metadata()
+                .map_err(|err| ctx.report(ScanProblem::walk(&err)))
+                .ok()
+                .filter(|m| filter.accepts_metadata(m))?;
+            let symlink = e.path_is_symlink();
+            let mut file = FileInfo::from_metadata(e.into_path(), &metadata);
+            file.symlink = symlink;
+            Some(file)
+        })
+        .inspect(|_| ctx.handle.add_files(1))
         .collect()
 }
 
-pub fn find_duplicates(files: &mut [FileInfo]) -> HashMap<Vec<u8>, Vec<PathBuf>> {
+/// Walks several roots as one set. A file reachable from overlapping roots
+/// is reported once, attributed to the most specific root.
+pub fn collect_roots(roots: &[ScanRoot], filter: &FileFilter, ctx: &ScanContext) -> Vec<FileInfo> {
+    let mut files: Vec<FileInfo> = Vec::new();
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+
+    for (root_idx, root) in roots.iter().enumerate() {
+        for mut file in collect_files(&root.path, filter, ctx) {
+            file.root = root_idx;
+            file.reference = root.reference;
+            match seen.get(&file.path) {
+                Some(&existing) => {
+                    let current = &roots[files[existing].root].path;
+                    if root.path.as_os_str().len() > current.as_os_str().len() {
+                        files[existing].root = root_idx;
+                        files[existing].reference = root.reference;
+                    }
+                }
+                None => {
+                    seen.insert(file.path.clone(), files.len());
+                    files.push(file);
+                }
+            }
+        }
+    }
+    collapse_links(files, ctx)
+}
+
+/// Folds paths sharing a device and inode into one logical file, so that
+/// existing hard links and followed symlinks are hashed once and never
+/// reported as duplicates. A real path is preferred over a symlink.
+pub fn collapse_links(files: Vec<FileInfo>, ctx: &ScanContext) -> Vec<FileInfo> {
+    let mut by_id: HashMap<(u64, u64), usize> = HashMap::new();
+    let mut collapsed: Vec<FileInfo> = Vec::with_capacity(files.len());
+    let mut aliases: Vec<(usize, PathBuf)> = Vec::new();
+
+    for mut file in files {
+        if file.inode == 0 {
+            collapsed.push(file);
+            continue;
+        }
+        let Some(&idx) = by_id.get(&(file.device, file.inode)) else {
+            by_id.insert((file.device, file.inode), collapsed.len());
+            collapsed.push(file);
+            continue;
+        };
+        let existing = &mut collapsed[idx];
+        // With a single link the second path can only come through a
+        // symlinked file or directory
+        if file.symlink || existing.symlink || file.nlink <= 1 {
+            if prefer_path(&file, existing) {
+                std::mem::swap(&mut existing.path, &mut file.path);
+                std::mem::swap(&mut existing.symlink, &mut file.symlink);
+                std::mem::swap(&mut existing.root, &mut file.root);
+            }
+            // Reachable from a reference root, so it stays protected
+            existing.reference |= file.reference;
+            aliases.push((idx, file.path));
+        } else {
+            // A link into a reference root shares its inode with a
+            // protected file, so the whole cluster is protected
+            existing.reference |= file.reference;
+            existing.links.push(file.path);
+        }
+    }
+
+    for (idx, link) in aliases {
+        ctx.record_symlink(link, collapsed[idx].path.clone());
+    }
+    collapsed
+}
+
+/// Whether `candidate` is a better path to report than `current` for the
+/// same file: a real path beats a link, a canonical one beats one that
+/// passes through a linked directory.
+fn prefer_path(candidate: &FileInfo, current: &FileInfo) -> bool {
+    if candidate.symlink != current.symlink {
+        return current.symlink;
+    }
+    let canonical = |path: &Path| fs::canonicalize(path).is_ok_and(|resolved| resolved == path);
+    !candidate.symlink && !canonical(&current.path) && canonical(&candidate.path)
+}
+
+pub fn find_duplicates(files: &mut [FileInfo], options: &HashOptions, ctx: &ScanContext) -> ScanResult {
+    let budget = MemoryBudget::new(options.memory_budget);
+    let mut stages = Vec::new();
+
     // First group by size to reduce hash calculations
-    let size_groups: HashMap<u64, Vec<&mut FileInfo>> = files
-        .iter_mut()
-        .fold(HashMap::new(), |mut map, file| {
-            map.entry(file.size).or_default().push(file);
-            map
+    ctx.handle.start_phase(ScanPhase::SizeGrouping, files.len() as u64, 0);
+    let all: Vec<Vec<usize>> = vec![(0..files.len()).collect()];
+    let mut groups = refine_groups(files, &all, HashStage::Size);
+    retain_scanned(files, &mut groups);
+    ctx.handle.add_files(files.len() as u64);
+    stages.push(stage_stats(files, HashStage::Size, &all, &groups, 0));
+
+    // Reuse hashes of unchanged files from earlier scans
+    let cache = ctx.cache.as_ref().filter(|_| options.use_cache);
+    let mut cache_stats = CacheStats::default();
+    if let Some(cache) = cache.filter(|_| !options.force_rehash) {
+        let mut selected = vec![false; files.len()];
+        for &idx in groups.iter().flatten() {
+            selected[idx] = true;
+        }
+        let candidates = files.iter_mut()
+            .enumerate()
+            .filter(|(idx, _)| selected[*idx])
+            .map(|(_, file)| file);
+        cache_stats = cache.lookup(candidates, options).unwrap_or_else(|err| {
+            ctx.report_io(cache.path(), ScanPhase::SizeGrouping, &err);
+            CacheStats::default()
         });
+    }
 
-    // Only hash files of the same size
-    let mut hash_map: HashMap<Vec<u8>, Vec<PathBuf>> = HashMap::new();
-    
-    size_groups.into_values()
-        .filter(|group| group.len() > 1)
-        .for_each(|group| {
-            group.into_par_iter()
-                .for_each(|file| {
-                    let _ = file.calculate_hash();
+    // Then only promote files that still collide after each cheaper stage
+    for stage in [HashStage::Head, HashStage::Tail, HashStage::Full] {
+        if ctx.handle.is_cancelled() {
+            break;
+        }
+        let bytes_read = hash_stage(files, &groups, stage, options, &budget, ctx);
+        let mut refined = refine_groups(files, &groups, stage);
+        retain_scanned(files, &mut refined);
+        stages.push(stage_stats(files, stage, &groups, &refined, bytes_read));
+        groups = refined;
+    }
+
+    if let Some(cache) = cache {
+        if let Err(err) = cache.store(files.iter(), options) {
+            ctx.report_io(cache.path(), ScanPhase::FullHash, &err);
+        }
+    }
+
+    if ctx.handle.is_cancelled() {
+        return ScanResult { stages, cache_stats, problems: ctx.problems_since(0), ..Default::default() };
+    }
+
+    let mut collisions = Vec::new();
+    if options.verify {
+        ctx.handle.start_phase(
+            ScanPhase::Verifying,
+            groups.iter().map(|group| group.len() as u64).sum(),
+            groups.iter().map(|group| 2 * files[group[0]].size * (group.len() as u64 - 1)).sum(),
+        );
+        let bytes_read = AtomicU64::new(0);
+        let verified: Vec<Vec<Vec<usize>>> = groups.par_iter()
+            .map(|group| split_by_content(files, group, options, &budget, ctx, &bytes_read))
+            .collect();
+
+        let mut confirmed = Vec::new();
+        for (group, classes) in groups.iter().zip(verified) {
+            if classes.len() > 1 {
+                // A hash collision: report the split, each class of identical
+                // files still forms a group of its own
+                collisions.push(HashCollision {
+                    hash: files[group[0]].hash.clone().unwrap_or_default(),
+                    groups: classes.iter()
+                        .map(|class| class.iter().map(|&idx| files[idx].path.clone()).collect())
+                        .collect(),
                 });
-        });
+            }
+            confirmed.extend(classes.into_iter().filter(|class| class.len() > 1));
+        }
+        retain_scanned(files, &mut confirmed);
+        stages.push(stage_stats(files, HashStage::Verify, &groups, &confirmed, bytes_read.into_inner()));
+        groups = confirmed;
+    }
 
-    // Group files by hash
-    files.iter()
-        .filter_map(|file| {
-            file.hash.as_ref().map(|hash| (hash.clone(), file.path.clone()))
-        })
-        .for_each(|(hash, path)| {
-            hash_map.entry(hash).or_default().push(path);
+    let devices = device_summaries(files, &groups);
+
+    let mut duplicates: Vec<DuplicateGroup> = groups.iter()
+        .filter_map(|group| DuplicateGroup::new(files, group))
+        .collect();
+    // Split classes share size and hash, number them in path order
+    duplicates.sort_by(|a, b| a.id.cmp(&b.id).then_with(|| a.members[0].path.cmp(&b.members[0].path)));
+    let mut classes: HashMap<u64, u64> = HashMap::new();
+    for group in &mut duplicates {
+        let class = classes.entry(group.id).or_default();
+        group.id = DuplicateGroup::id_for(group.size, &group.hash, *class);
+        *class += 1;
+    }
+    sort_groups(&mut duplicates, GroupSort::default());
+
+    let hardlinks = files.iter()
+        .filter(|file| !file.links.is_empty())
+        .map(|file| (file.path.clone(), file.links.clone()))
+        .collect();
+
+    let symlinks = ctx.take_symlinks();
+    let deduplicated = std::mem::take(&mut *ctx.deduplicated.lock().unwrap());
+    let problems = ctx.problems_since(0);
+
+    ScanResult {
+        duplicates,
+        collisions,
+        stages,
+        cache_stats,
+        hardlinks,
+        symlinks,
+        deduplicated,
+        devices,
+        problems,
+        ..Default::default()
+    }
+}
+
+fn device_summaries(files: &[FileInfo], groups: &[Vec<usize>]) -> Vec<DeviceSummary> {
+    let mut devices: HashMap<u64, DeviceSummary> = HashMap::new();
+    for file in files {
+        let summary = devices.entry(file.device).or_insert_with(|| DeviceSummary {
+            device: file.device,
+            example: file.path.clone(),
+            ..Default::default()
         });
+        summary.files += 1;
+    }
 
-    // Only keep groups with duplicates
-    hash_map.retain(|_, paths| paths.len() > 1);
-    hash_map
+    for group in groups {
+        let mut per_device: HashMap<u64, u64> = HashMap::new();
+        for &idx in group {
+            *per_device.entry(files[idx].device).or_default() += 1;
+        }
+        let size = files[group[0]].size;
+        for (device, count) in per_device {
+            if let Some(summary) = devices.get_mut(&device) {
+                summary.groups += 1;
+                summary.duplicate_files += count as usize;
+                summary.reclaimable += size * (count - 1);
+            }
+        }
+    }
+
+    // Only probe where reflinks could be used
+    for summary in devices.values_mut().filter(|summary| summary.groups > 0) {
+        summary.reflink = reflink::supported(&summary.example);
+    }
+
+    let mut devices: Vec<DeviceSummary> = devices.into_values().collect();
+    devices.sort_by_key(|summary| summary.device);
+    devices
+}
+
+/// Drops groups made up only of reference files; nothing in them could be
+/// acted on, so there is no point hashing or reporting them.
+fn retain_scanned(files: &[FileInfo], groups: &mut Vec<Vec<usize>>) {
+    groups.retain(|group| group.iter().any(|&idx| !files[idx].reference));
 }
 
-pub fn should_ignore(path: &Path, filters: &[String]) -> bool {
-    if let Some(ext) = path.extension() {
-        if let Some(ext_str) = ext.to_str() {
-            return filters.iter().any(|filter| filter == ext_str);
+/// Partitions a group into classes of byte-identical files by comparing
+/// the remaining members against the first one, repeatedly. Files that
+/// cannot be read are dropped; if the first one fails, the next takes its
+/// place rather than the others being blamed.
+fn split_by_content(files: &[FileInfo], group: &[usize], options: &HashOptions, budget: &MemoryBudget, ctx: &ScanContext, bytes_read: &AtomicU64) -> Vec<Vec<usize>> {
+    let handle = &ctx.handle;
+    let mut classes = Vec::new();
+    let mut remaining = group.to_vec();
+
+    while !remaining.is_empty() {
+        let first = remaining.remove(0);
+        if let Err(err) = fs::File::open(&files[first].path) {
+            ctx.report_io(&files[first].path, ScanPhase::Verifying, &err);
+            handle.add_files(1);
+            continue;
+        }
+
+        let mut class = vec![first];
+        let mut different = Vec::new();
+        let mut first_failed = false;
+        for idx in remaining {
+            if first_failed {
+                different.push(idx);
+                continue;
+            }
+            let permit = budget.acquire(2 * buffer_len(files[idx].size, options.chunk_size));
+            match files_equal(&files[first].path, &files[idx].path, permit.bytes() / 2, handle) {
+                Ok(true) => class.push(idx),
+                Ok(false) => different.push(idx),
+                // The first file went away during the comparison
+                Err(err) if fs::File::open(&files[first].path).is_err() => {
+                    ctx.report_io(&files[first].path, ScanPhase::Verifying, &err);
+                    first_failed = true;
+                    different.push(idx);
+                    continue;
+                }
+                Err(err) => ctx.report_io(&files[idx].path, ScanPhase::Verifying, &err),
+            }
+            bytes_read.fetch_add(2 * files[idx].size, Ordering::Relaxed);
+            handle.add_files(1);
+        }
+        handle.add_files(1);
+        if first_failed {
+            // Matched against a file that is gone, so compare them again
+            class.remove(0);
+            class.append(&mut different);
+            remaining = class;
+        } else {
+            classes.push(class);
+            remaining = different;
         }
     }
-    false
+    classes
+}
+
+/// Compares two files chunk by chunk, stopping at the first difference.
+pub fn files_equal(a: &Path, b: &Path, chunk_size: usize, handle: &ScanHandle) -> io::Result<bool> {
+    let mut file_a = fs::File::open(a)?;
+    let mut file_b = fs::File::open(b)?;
+    if file_a.metadata()?.len() != file_b.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let mut buffer_a = vec![0; chunk_size.max(1)];
+    let mut buffer_b = vec![0; chunk_size.max(1)];
+    loop {
+        handle.check()?;
+        let n = read_full(&mut file_a, &mut buffer_a)?;
+        let m = read_full(&mut file_b, &mut buffer_b)?;
+        handle.add_bytes((n + m) as u64);
+        if buffer_a[..n] != buffer_b[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fills `buffer` as far as possible, returning less only at end of file.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn hash_stage(files: &mut [FileInfo], groups: &[Vec<usize>], stage: HashStage, options: &HashOptions, budget: &MemoryBudget, ctx: &ScanContext) -> u64 {
+    let handle = &ctx.handle;
+    let mut selected = vec![false; files.len()];
+    for &idx in groups.iter().flatten() {
+        selected[idx] = true;
+    }
+
+    let phase = match stage {
+        HashStage::Full => ScanPhase::FullHash,
+        _ => ScanPhase::PartialHash,
+    };
+    let pending: u64 = groups.iter().flatten().map(|&idx| files[idx].pending_read(stage, options)).sum();
+    handle.start_phase(phase, groups.iter().map(|group| group.len() as u64).sum(), pending);
+
+    let bytes_read = AtomicU64::new(0);
+    files.par_iter_mut()
+        .enumerate()
+        .filter(|(idx, _)| selected[*idx])
+        .for_each(|(_, file)| {
+            if !handle.checkpoint() {
+                return;
+            }
+            let len = match stage {
+                HashStage::Full => file.size,
+                _ => file.size.min(options.partial_size as u64),
+            };
+            let permit = budget.acquire(buffer_len(len, options.chunk_size));
+            let mut buffer = vec![0; permit.bytes()];
+            match file.calculate_stage_hash(stage, options, handle, &mut buffer) {
+                Ok(read) => {
+                    bytes_read.fetch_add(read, Ordering::Relaxed);
+                }
+                Err(err) => ctx.report_io(&file.path, phase, &err),
+            }
+            handle.add_files(1);
+        });
+    bytes_read.into_inner()
+}
+
+/// Splits every group by the key of `stage`, dropping files without a key
+/// and groups that no longer collide.
+fn refine_groups(files: &[FileInfo], groups: &[Vec<usize>], stage: HashStage) -> Vec<Vec<usize>> {
+    groups.iter()
+        .flat_map(|group| {
+            let mut split: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+            for &idx in group {
+                if let Some(key) = files[idx].stage_key(stage) {
+                    split.entry(key).or_default().push(idx);
+                }
+            }
+            split.into_values()
+        })
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+fn stage_stats(files: &[FileInfo], stage: HashStage, before: &[Vec<usize>], after: &[Vec<usize>], bytes_read: u64) -> StageStats {
+    let total_size = |groups: &[Vec<usize>]| -> u64 {
+        groups.iter().flatten().map(|&idx| files[idx].size).sum()
+    };
+    StageStats {
+        stage,
+        candidates: before.iter().map(Vec::len).sum(),
+        remaining: after.iter().map(Vec::len).sum(),
+        bytes_read,
+        bytes_saved: total_size(before) - total_size(after),
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Hex representation of a content hash.
+pub fn format_hash(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 pub fn create_hardlink(src: &Path, dst: &Path) -> io::Result<()> {
     fs::hard_link(src, dst)
 }
 
+/// Replaces `duplicate` with a hard link to `keeper` without the path ever
+/// going missing: the link is made under a temporary name next to the
+/// duplicate, checked against the duplicate's content and renamed over it.
+/// With `preserve_permissions` the shared inode takes on the duplicate's
+/// mode and ownership, which then applies to the keeper as well.
+pub fn replace_with_hardlink(keeper: &Path, duplicate: &Path, preserve_permissions: bool) -> io::Result<()> {
+    let metadata = fs::metadata(duplicate)?;
+    let (device, inode, _) = file_id(&metadata);
+    let (keeper_device, keeper_inode, _) = file_id(&fs::metadata(keeper)?);
+    if inode != 0 && (keeper_device, keeper_inode) == (device, inode) {
+        // Linked already, say by running the same plan twice
+        return Ok(());
+    }
+    let ownership = preserve_permissions.then(|| Ownership::of(&metadata));
+    let previous = match ownership {
+        Some(_) => Some(Ownership::of(&fs::metadata(keeper)?)),
+        None => None,
+    };
+    let temp = create_sibling(duplicate, |temp| create_hardlink(keeper, temp)).map_err(|e| {
+        if e.kind() == io::ErrorKind::CrossesDevices {
+            io::Error::new(e.kind(), format!(
+                "{} and {} are on different file systems, hard links cannot cross devices",
+                keeper.display(),
+                duplicate.display(),
+            ))
+        } else {
+            e
+        }
+    })?;
+
+    let result = finish_replace(&temp, duplicate, ownership.as_ref());
+    if result.is_err() {
+        // The duplicate's ownership may already be on the shared inode
+        if let Some(previous) = previous {
+            let _ = previous.apply(&temp);
+        }
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Replaces `duplicate` with a symbolic link to `keeper`, written relative
+/// to the duplicate's directory if `relative` is set. As with
+/// [`replace_with_hardlink`] the link is made under a temporary name,
+/// checked to resolve to the keeper and renamed over the duplicate.
+pub fn replace_with_symlink(keeper: &Path, duplicate: &Path, relative: bool) -> io::Result<()> {
+    let keeper = keeper.canonicalize()?;
+    let dir = duplicate.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let target = if relative {
+        relative_path(&dir.canonicalize()?, &keeper)
+    } else {
+        keeper.clone()
+    };
+    let temp = create_sibling(duplicate, |temp| create_symlink(&target, temp))?;
+
+    let result = if fs::canonicalize(&temp).is_ok_and(|resolved| resolved == keeper) {
+        finish_replace(&temp, duplicate, None)
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "the link does not resolve to the kept copy"))
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// The path leading from directory `from` to `to`, both canonical.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let common = from.components().zip(to.components()).take_while(|(a, b)| a == b).count();
+    let mut path: PathBuf = from.components().skip(common).map(|_| Component::ParentDir).collect();
+    path.extend(to.components().skip(common));
+    path
+}
+
+/// Creates a hidden temporary entry in the directory of `path`, retrying
+/// with another name while `create` reports that the name is taken.
+fn create_sibling(path: &Path, mut create: impl FnMut(&Path) -> io::Result<()>) -> io::Result<PathBuf> {
+    let name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let mut attempt = 0;
+    loop {
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".dupfi-{}-{}", std::process::id(), attempt));
+        let temp = path.with_file_name(temp_name);
+        match create(&temp) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            result => return result.map(|_| temp),
+        }
+    }
+}
+
+fn finish_replace(temp: &Path, duplicate: &Path, ownership: Option<&Ownership>) -> io::Result<()> {
+    // The duplicate may have been edited since the scan
+    if !files_equal(temp, duplicate, DEFAULT_CHUNK_SIZE, &ScanHandle::new())? {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "content no longer matches the kept copy"));
+    }
+    if let Some(ownership) = ownership {
+        ownership.apply(temp)?;
+    }
+    fs::rename(temp, duplicate)
+}
+
+/// Mode and owner of a file. Preserving them on a hard link changes every
+/// name of the inode, so the journal keeps the keeper's old ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ownership {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Ownership {
+    #[cfg(unix)]
+    pub fn of(metadata: &fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self { mode: metadata.mode() & 0o7777, uid: metadata.uid(), gid: metadata.gid() }
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(metadata: &fs::Metadata) -> Self {
+        let mode = if metadata.permissions().readonly() { 0o444 } else { 0o644 };
+        Self { mode, uid: 0, gid: 0 }
+    }
+
+    /// Changing the owner clears set-id bits, so it goes first.
+    #[cfg(unix)]
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let current = fs::metadata(path)?;
+        if (current.uid(), current.gid()) != (self.uid, self.gid) {
+            std::os::unix::fs::chown(path, Some(self.uid), Some(self.gid))?;
+        }
+        fs::set_permissions(path, fs::Permissions::from_mode(self.mode))
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, path: &Path) -> io::Result<()> {
+        let mut permissions = fs::metadata(path)?.permissions();
+        permissions.set_readonly(self.mode & 0o222 == 0);
+        fs::set_permissions(path, permissions)
+    }
+}
+
+/// Whether two paths are hard links to one inode. Never true where inodes
+/// are unknown.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    let id = |path: &Path| fs::symlink_metadata(path).map(|metadata| file_id(&metadata));
+    matches!((id(a), id(b)), (Ok((device_a, inode_a, _)), Ok((device_b, inode_b, _)))
+        if inode_a != 0 && (device_a, inode_a) == (device_b, inode_b))
+}
+
+/// Creates `dst` as a symbolic link pointing at `src`.
+pub fn create_symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(src, dst);
+    #[cfg(windows)]
+    return std::os::windows::fs::symlink_file(src, dst);
+}
+
+/// Moves a file, refusing to replace anything already at `dst`, even a
+/// file that appears there while the move is under way.
 pub fn move_file(src: &Path, dst: &Path) -> io::Result<()> {
     if let Some(parent) = dst.parent() {
         fs::create_dir_all(parent)?;
     }
-    fs::rename(src, dst)
+    let result = match rename_noreplace(src, dst) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => copy_new(src, dst).and_then(|_| fs::remove_file(src)),
+        result => result,
+    };
+    result.map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => io::Error::new(e.kind(), format!("{} already exists", dst.display())),
+        _ => e,
+    })
+}
+
+/// `rename` that fails instead of replacing `dst`.
+#[cfg(target_os = "linux")]
+pub fn rename_noreplace(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let c_path = |path: &Path| CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"));
+    let (c_src, c_dst) = (c_path(src)?, c_path(dst)?);
+    let renamed = unsafe {
+        libc::renameat2(libc::AT_FDCWD, c_src.as_ptr(), libc::AT_FDCWD, c_dst.as_ptr(), libc::RENAME_NOREPLACE)
+    };
+    if renamed == 0 {
+        return Ok(());
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        // The kernel or file system lacks the flag
+        Some(libc::EINVAL | libc::ENOSYS) => link_and_remove(src, dst),
+        _ => Err(err),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rename_noreplace(src: &Path, dst: &Path) -> io::Result<()> {
+    link_and_remove(src, dst)
+}
+
+/// Unlike `rename`, making a hard link never replaces `dst`.
+fn link_and_remove(src: &Path, dst: &Path) -> io::Result<()> {
+    match fs::hard_link(src, dst) {
+        Ok(()) => fs::remove_file(src),
+        Err(e) if matches!(e.kind(), io::ErrorKind::AlreadyExists | io::ErrorKind::CrossesDevices) => Err(e),
+        // No hard links on this file system
+        Err(_) => copy_new(src, dst).and_then(|_| fs::remove_file(src)),
+    }
+}
+
+/// Copies `src` into a newly created `dst`, leaving an existing file alone.
+fn copy_new(src: &Path, dst: &Path) -> io::Result<()> {
+    let mut reader = fs::File::open(src)?;
+    let mut writer = fs::OpenOptions::new().write(true).create_new(true).open(dst)?;
+    let result = io::copy(&mut reader, &mut writer)
+        .and_then(|_| writer.set_permissions(reader.metadata()?.permissions()));
+    if result.is_err() {
+        let _ = fs::remove_file(dst);
+    }
+    result
 }
 
 #[cfg(test)]
@@ -105,6 +1238,8 @@ mod tests {
     use super::*;
     use tempfile::tempdir;
     use std::io::Write;
+    use sha2::{Digest, Sha256};
+    use crate::{filters::{FilterRule, RuleKind}, problems::ProblemKind};
 
     #[test]
     fn test_find_duplicates() {
@@ -128,12 +1263,12 @@ mod tests {
             FileInfo::new(file3_path).unwrap(),
         ];
         
-        let duplicates = find_duplicates(&mut files);
+        let duplicates = find_duplicates(&mut files, &HashOptions::default(), &ScanContext::default()).duplicates;
         
         assert_eq!(duplicates.len(), 1); // One group of duplicates
         
-        for paths in duplicates.values() {
-            assert_eq!(paths.len(), 2); // Each group should have 2 files
+        for group in &duplicates {
+            assert_eq!(group.members.len(), 2); // Each group should have 2 files
         }
     }
 
@@ -148,8 +1283,12 @@ mod tests {
         fs::File::create(&txt_file).unwrap();
         fs::File::create(&doc_file).unwrap();
         
-        let filters = vec!["doc".to_string()];
-        let files = collect_files(temp_dir.path(), &filters);
+        let options = FilterOptions {
+            rules: vec![FilterRule::exclude(RuleKind::Extension, "doc")],
+            ..Default::default()
+        };
+        let filter = FileFilter::new(&options).unwrap();
+        let files = collect_files(temp_dir.path(), &filter, &ScanContext::default());
         
         assert_eq!(files.len(), 1);
         assert_eq!(files[0].path.extension().unwrap(), "txt");
@@ -170,4 +1309,611 @@ mod tests {
         assert!(linked.exists());
         assert_eq!(fs::read(&original).unwrap(), fs::read(&linked).unwrap());
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_with_hardlink() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let temp_dir = tempdir().unwrap();
+        let keeper = temp_dir.path().join("keeper.txt");
+        let duplicate = temp_dir.path().join("duplicate.txt");
+        fs::write(&keeper, b"same").unwrap();
+        fs::write(&duplicate, b"same").unwrap();
+        fs::set_permissions(&duplicate, fs::Permissions::from_mode(0o600)).unwrap();
+
+        replace_with_hardlink(&keeper, &duplicate, true).unwrap();
+        let (kept, linked) = (fs::metadata(&keeper).unwrap(), fs::metadata(&duplicate).unwrap());
+        assert_eq!(kept.ino(), linked.ino());
+        assert_eq!(linked.mode() & 0o777, 0o600);
+        // No temporary names are left behind, not even when linking again
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+        replace_with_hardlink(&keeper, &duplicate, true).unwrap();
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+
+        // A duplicate edited after the scan is left alone
+        let edited = temp_dir.path().join("edited.txt");
+        fs::write(&edited, b"diff").unwrap();
+        let err = replace_with_hardlink(&keeper, &edited, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&edited).unwrap(), b"diff");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_move_file_never_overwrites() {
+        let temp_dir = tempdir().unwrap();
+        let src = temp_dir.path().join("src.txt");
+        let dst = temp_dir.path().join("out").join("dst.txt");
+        fs::write(&src, b"moved").unwrap();
+        move_file(&src, &dst).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read(&dst).unwrap(), b"moved");
+
+        fs::write(&src, b"other").unwrap();
+        assert_eq!(move_file(&src, &dst).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        // The copying fallback for other devices refuses as well
+        assert_eq!(copy_new(&src, &dst).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&src).unwrap(), b"other");
+        assert_eq!(fs::read(&dst).unwrap(), b"moved");
+    }
+
+    #[test]
+    fn test_streaming_hash_matches_one_shot_digest() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("large.bin");
+
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &content).unwrap();
+
+        let mut file = FileInfo::new(path).unwrap();
+        let mut buffer = vec![0; 4096];
+        file.calculate_hash(HashAlgorithm::Sha256, &ScanHandle::new(), &mut buffer).unwrap();
+
+        assert_eq!(file.hash.unwrap(), Sha256::digest(&content).to_vec());
+    }
+
+    #[test]
+    fn test_find_duplicates_with_small_budget() {
+        let temp_dir = tempdir().unwrap();
+
+        let content = vec![7u8; 50_000];
+        let mut files = Vec::new();
+        for name in ["a.bin", "b.bin", "c.bin"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, &content).unwrap();
+            files.push(FileInfo::new(path).unwrap());
+        }
+
+        // Budget smaller than a single chunk must still make progress
+        let options = HashOptions { chunk_size: 8192, memory_budget: 1000, ..Default::default() };
+        let duplicates = find_duplicates(&mut files, &options, &ScanContext::default()).duplicates;
+
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].members.len(), 3);
+    }
+
+    #[test]
+    fn test_stages_eliminate_partial_mismatches() {
+        let temp_dir = tempdir().unwrap();
+        let base = vec![0u8; 64 * 1024];
+
+        let mut variants = Vec::new();
+        for (name, offset, value) in [("head_a", 0, 1u8), ("head_b", 0, 2), ("tail_a", base.len() - 1, 1), ("tail_b", base.len() - 1, 2)] {
+            let mut content = base.clone();
+            content[offset] = value;
+            variants.push((name, content));
+        }
+        variants.push(("same_a", base.clone()));
+        variants.push(("same_b", base.clone()));
+
+        let mut files: Vec<FileInfo> = variants.iter()
+            .map(|(name, content)| {
+                let path = temp_dir.path().join(name);
+                fs::write(&path, content).unwrap();
+                FileInfo::new(path).unwrap()
+            })
+            .collect();
+
+        let options = HashOptions { partial_size: 4096, ..Default::default() };
+        let result = find_duplicates(&mut files, &options, &ScanContext::default());
+
+        assert_eq!(result.duplicates.len(), 1);
+        let remaining: Vec<usize> = result.stages.iter().map(|s| s.remaining).collect();
+        assert_eq!(remaining, vec![6, 4, 2, 2]);
+
+        let head = &result.stages[1];
+        assert_eq!(head.stage, HashStage::Head);
+        assert_eq!(head.bytes_read, 6 * 4096);
+        assert_eq!(head.bytes_saved, 2 * base.len() as u64);
+
+        // Only the two real duplicates are ever read in full
+        assert_eq!(result.stages[3].bytes_read, 2 * base.len() as u64);
+    }
+
+    #[test]
+    fn test_small_files_skip_full_read() {
+        let temp_dir = tempdir().unwrap();
+        let mut files = Vec::new();
+        for name in ["a.txt", "b.txt"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, b"tiny").unwrap();
+            files.push(FileInfo::new(path).unwrap());
+        }
+
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ScanContext::default());
+
+        assert_eq!(result.duplicates.len(), 1);
+        assert_eq!(result.stages[2].bytes_read, 0);
+        assert_eq!(result.stages[3].bytes_read, 0);
+        assert_eq!(files[0].hash.as_ref().unwrap(), &Sha256::digest(b"tiny").to_vec());
+    }
+
+    #[test]
+    fn test_every_algorithm_finds_duplicates() {
+        let temp_dir = tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a.txt", "b.txt", "c.txt"].iter()
+            .map(|name| temp_dir.path().join(name))
+            .collect();
+        fs::write(&paths[0], b"same content").unwrap();
+        fs::write(&paths[1], b"same content").unwrap();
+        fs::write(&paths[2], b"other stuff!").unwrap();
+
+        for algorithm in HashAlgorithm::ALL {
+            let mut files: Vec<FileInfo> = paths.iter()
+                .map(|p| FileInfo::new(p.clone()).unwrap())
+                .collect();
+            let options = HashOptions { algorithm, ..Default::default() };
+            let result = find_duplicates(&mut files, &options, &ScanContext::default());
+
+            assert_eq!(result.duplicates.len(), 1, "{}", algorithm.label());
+            assert_eq!(result.duplicates[0].members.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_verify_splits_hash_collisions() {
+        let temp_dir = tempdir().unwrap();
+
+        // "plumless" and "buckeroo" share the same CRC32
+        let mut files = Vec::new();
+        for (name, content) in [("a.txt", "plumless"), ("b.txt", "buckeroo"), ("c.txt", "plumless")] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            files.push(FileInfo::new(path).unwrap());
+        }
+
+        let options = HashOptions { algorithm: HashAlgorithm::Crc32, ..Default::default() };
+        let unverified = find_duplicates(&mut files.clone(), &options, &ScanContext::default());
+        assert_eq!(unverified.duplicates[0].members.len(), 3);
+
+        let options = HashOptions { verify: true, ..options };
+        let result = find_duplicates(&mut files, &options, &ScanContext::default());
+
+        // The identical pair stays a group, the collision is still flagged
+        assert_eq!(result.duplicates.len(), 1);
+        let paths: Vec<_> = result.duplicates[0].members.iter().map(|member| member.path.file_name().unwrap()).collect();
+        assert_eq!(paths, ["a.txt", "c.txt"]);
+        assert_eq!(result.collisions.len(), 1);
+        let mut sizes: Vec<usize> = result.collisions[0].groups.iter().map(Vec::len).collect();
+        sizes.sort();
+        assert_eq!(sizes, vec![1, 2]);
+        assert_eq!(result.stages.last().unwrap().stage, HashStage::Verify);
+
+        // Two classes of one hash get IDs of their own
+        let path = temp_dir.path().join("d.txt");
+        fs::write(&path, "buckeroo").unwrap();
+        files.push(FileInfo::new(path).unwrap());
+        let result = find_duplicates(&mut files, &options, &ScanContext::default());
+        assert_eq!(result.duplicates.len(), 2);
+        assert_ne!(result.duplicates[0].id, result.duplicates[1].id);
+    }
+
+    #[test]
+    fn test_unreadable_first_member_is_skipped() {
+        let temp_dir = tempdir().unwrap();
+        let mut files = Vec::new();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, "same").unwrap();
+            files.push(FileInfo::new(path).unwrap());
+        }
+        fs::remove_file(&files[0].path).unwrap();
+
+        let ctx = ScanContext::default();
+        let budget = MemoryBudget::new(1 << 20);
+        let classes = split_by_content(&files, &[0, 1, 2], &HashOptions::default(), &budget, &ctx, &AtomicU64::new(0));
+        assert_eq!(classes, vec![vec![1, 2]]);
+        let problems = ctx.problems_since(0);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path.as_deref(), Some(files[0].path.as_path()));
+    }
+
+    #[test]
+    fn test_files_equal() {
+        let temp_dir = tempdir().unwrap();
+        let a = temp_dir.path().join("a");
+        let b = temp_dir.path().join("b");
+        let c = temp_dir.path().join("c");
+        fs::write(&a, vec![1u8; 10_000]).unwrap();
+        fs::write(&b, vec![1u8; 10_000]).unwrap();
+        let mut different = vec![1u8; 10_000];
+        different[9_999] = 2;
+        fs::write(&c, different).unwrap();
+
+        let handle = ScanHandle::new();
+        assert!(files_equal(&a, &b, 1024, &handle).unwrap());
+        assert!(!files_equal(&a, &c, 1024, &handle).unwrap());
+    }
+
+    #[test]
+    fn test_cache_skips_rehashing_unchanged_files() {
+        let temp_dir = tempdir().unwrap();
+        let ctx = ScanContext {
+            cache: Some(Arc::new(HashCache::open(&temp_dir.path().join("cache.sqlite")).unwrap())),
+            ..Default::default()
+        };
+
+        let content = vec![3u8; 100_000];
+        let paths: Vec<PathBuf> = ["a.bin", "b.bin"].iter()
+            .map(|name| temp_dir.path().join(name))
+            .collect();
+        for path in &paths {
+            fs::write(path, &content).unwrap();
+        }
+        let scan = |options: &HashOptions| {
+            let mut files: Vec<FileInfo> = paths.iter().map(|p| FileInfo::new(p.clone()).unwrap()).collect();
+            find_duplicates(&mut files, options, &ctx)
+        };
+
+        let first = scan(&HashOptions::default());
+        assert_eq!(first.cache_stats.misses, 2);
+        assert_eq!(first.stages[3].bytes_read, 2 * content.len() as u64);
+
+        let second = scan(&HashOptions::default());
+        assert_eq!(second.cache_stats.hits, 2);
+        assert_eq!(second.duplicates, first.duplicates);
+        assert!(second.stages.iter().all(|stage| stage.bytes_read == 0));
+
+        let forced = scan(&HashOptions { force_rehash: true, ..Default::default() });
+        assert_eq!(forced.cache_stats, CacheStats::default());
+        assert_eq!(forced.stages[3].bytes_read, 2 * content.len() as u64);
+    }
+
+    #[test]
+    fn test_cancelled_scan_stops_early() {
+        let temp_dir = tempdir().unwrap();
+        for name in ["a.txt", "b.txt"] {
+            fs::write(temp_dir.path().join(name), b"same").unwrap();
+        }
+
+        let ctx = ScanContext::default();
+        ctx.handle.cancel();
+
+        assert!(collect_files(temp_dir.path(), &FileFilter::default(), &ctx).is_empty());
+
+        let mut files = collect_files(temp_dir.path(), &FileFilter::default(), &ScanContext::default());
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+        assert!(result.duplicates.is_empty());
+        assert!(files.iter().all(|file| file.hash.is_none()));
+    }
+
+    #[test]
+    fn test_progress_tracks_hashed_bytes() {
+        let temp_dir = tempdir().unwrap();
+        let content = vec![9u8; 40_000];
+        for name in ["a.bin", "b.bin"] {
+            fs::write(temp_dir.path().join(name), &content).unwrap();
+        }
+
+        let ctx = ScanContext::default();
+        let mut files = collect_files(temp_dir.path(), &FileFilter::default(), &ctx);
+        assert_eq!(ctx.handle.progress().files_done, 2);
+
+        let options = HashOptions { partial_size: 1024, ..Default::default() };
+        find_duplicates(&mut files, &options, &ctx);
+
+        let progress = ctx.handle.progress();
+        assert_eq!(progress.phase, ScanPhase::FullHash);
+        assert_eq!(progress.files_done, 2);
+        assert_eq!(progress.bytes_done, 2 * content.len() as u64);
+        assert_eq!(progress.fraction(), 1.0);
+    }
+
+    #[test]
+    fn test_multiple_roots_are_deduplicated_together() {
+        let import = tempdir().unwrap();
+        let archive = tempdir().unwrap();
+        fs::write(import.path().join("photo.jpg"), b"same picture").unwrap();
+        fs::create_dir(archive.path().join("2023")).unwrap();
+        fs::write(archive.path().join("2023").join("photo.jpg"), b"same picture").unwrap();
+
+        // The nested root overlaps the archive root and must not double count
+        let roots = vec![
+            ScanRoot::new(import.path(), false),
+            ScanRoot::new(archive.path(), false),
+            ScanRoot::new(archive.path().join("2023"), false),
+        ];
+        let ctx = ScanContext::default();
+        let mut files = collect_roots(&roots, &FileFilter::default(), &ctx);
+        assert_eq!(files.len(), 2);
+
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+        let members = &result.duplicates[0].members;
+        assert_eq!(members.len(), 2);
+        let root_of = |path: PathBuf| members.iter().find(|member| member.path == path).map(|member| member.root);
+        assert_eq!(root_of(import.path().join("photo.jpg")), Some(0));
+        assert_eq!(root_of(archive.path().join("2023").join("photo.jpg")), Some(2));
+    }
+
+    #[test]
+    fn test_reference_only_groups_are_dropped() {
+        let import = tempdir().unwrap();
+        let archive = tempdir().unwrap();
+        fs::write(import.path().join("new.jpg"), b"imported picture").unwrap();
+        fs::write(archive.path().join("old.jpg"), b"imported picture").unwrap();
+        fs::write(archive.path().join("a.txt"), b"archived twice").unwrap();
+        fs::write(archive.path().join("b.txt"), b"archived twice").unwrap();
+
+        let roots = vec![ScanRoot::new(import.path(), false), ScanRoot::new(archive.path(), true)];
+        let ctx = ScanContext::default();
+        let mut files = collect_roots(&roots, &FileFilter::default(), &ctx);
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+
+        assert_eq!(result.duplicates.len(), 1);
+        let reference_of = |path: PathBuf| result.duplicates[0].members.iter()
+            .find(|member| member.path == path)
+            .map(|member| member.reference);
+        assert_eq!(reference_of(archive.path().join("old.jpg")), Some(true));
+        assert_eq!(reference_of(import.path().join("new.jpg")), Some(false));
+    }
+
+    #[test]
+    fn test_ignore_files_prune_traversal() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("target").join("debug")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join("target").join("debug").join("app"), b"artifact").unwrap();
+        fs::write(root.join("src").join("main.rs"), b"fn main() {}").unwrap();
+
+        let mut options = FilterOptions::default();
+        options.ignore_files.gitignore = true;
+        let filter = FileFilter::new(&options).unwrap();
+        let files = collect_files(root, &filter, &ScanContext::default());
+        let names: Vec<_> = files.iter().map(|f| f.path.file_name().unwrap().to_owned()).collect();
+        assert_eq!(names.len(), 2);
+        assert!(!names.contains(&"app".into()));
+    }
+
+    #[test]
+    fn test_existing_hardlinks_are_not_duplicates() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("original.bin"), b"shared content").unwrap();
+        fs::hard_link(root.join("original.bin"), root.join("linked.bin")).unwrap();
+
+        let roots = vec![ScanRoot::new(root, false)];
+        let ctx = ScanContext::default();
+        let mut files = collect_roots(&roots, &FileFilter::default(), &ctx);
+        assert_eq!(files.len(), 1);
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+        assert!(result.duplicates.is_empty());
+        assert_eq!(result.hardlinks.len(), 1);
+
+        // A real copy makes a group, with the link cluster still one member
+        fs::write(root.join("copy.bin"), b"shared content").unwrap();
+        let mut files = collect_roots(&roots, &FileFilter::default(), &ctx);
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+        assert_eq!(result.duplicates[0].members.len(), 2);
+    }
+
+    #[test]
+    fn test_hardlinks_into_reference_roots_stay_protected() {
+        let import = tempdir().unwrap();
+        let archive = tempdir().unwrap();
+        fs::write(archive.path().join("photo.jpg"), b"same picture").unwrap();
+        fs::hard_link(archive.path().join("photo.jpg"), import.path().join("linked.jpg")).unwrap();
+        fs::write(import.path().join("copy.jpg"), b"same picture").unwrap();
+
+        // The normal root's link is seen first and keeps its path
+        let roots = vec![ScanRoot::new(import.path(), false), ScanRoot::new(archive.path(), true)];
+        let ctx = ScanContext::default();
+        let mut files = collect_roots(&roots, &FileFilter::default(), &ctx);
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+
+        let group = &result.duplicates[0];
+        let linked = group.members.iter().find(|member| member.path == import.path().join("linked.jpg")).unwrap();
+        assert!(linked.reference);
+        assert_eq!(group.members[group.keeper].path, import.path().join("linked.jpg"));
+        let mut selection = crate::batch::Selection::default();
+        selection.select_where(&result.duplicates, |_| true);
+        assert!(!selection.contains(&import.path().join("linked.jpg")));
+        assert!(selection.contains(&import.path().join("copy.jpg")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policies() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("data")).unwrap();
+        fs::write(root.join("data").join("file.txt"), b"linked content").unwrap();
+        std::os::unix::fs::symlink(root.join("data").join("file.txt"), root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("data"), root.join("data-link")).unwrap();
+        // A loop must not hang the walk
+        std::os::unix::fs::symlink(root, root.join("data").join("loop")).unwrap();
+
+        let scan = |policy: SymlinkPolicy| {
+            let options = FilterOptions { symlinks: policy, ..Default::default() };
+            let filter = FileFilter::new(&options).unwrap();
+            let roots = vec![ScanRoot::new(root, false)];
+            let ctx = ScanContext::default();
+            let mut files = collect_roots(&roots, &filter, &ctx);
+            let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+            (files, result)
+        };
+
+        let (files, result) = scan(SymlinkPolicy::Ignore);
+        assert_eq!(files.len(), 1);
+        assert!(result.symlinks.is_empty());
+
+        let (files, result) = scan(SymlinkPolicy::Follow);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, root.join("data").join("file.txt"));
+        assert!(result.duplicates.is_empty());
+        assert_eq!(result.symlinks.len(), 2);
+
+        let (files, result) = scan(SymlinkPolicy::Report);
+        assert_eq!(files.len(), 1);
+        assert!(result.duplicates.is_empty());
+        assert_eq!(result.symlinks.get(&root.join("link.txt")), Some(&fs::canonicalize(&files[0].path).unwrap()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_followed_links_into_reference_roots_stay_protected() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        let (import, archive) = (root.join("import"), root.join("archive"));
+        fs::create_dir(&import).unwrap();
+        fs::create_dir(&archive).unwrap();
+        fs::write(archive.join("photo.jpg"), b"same picture").unwrap();
+        fs::write(import.join("copy.jpg"), b"same picture").unwrap();
+        std::os::unix::fs::symlink(archive.join("photo.jpg"), import.join("link.jpg")).unwrap();
+
+        // The normal root comes first, so the link is seen before the file
+        let options = FilterOptions { symlinks: SymlinkPolicy::Follow, ..Default::default() };
+        let filter = FileFilter::new(&options).unwrap();
+        let roots = vec![ScanRoot::new(&import, false), ScanRoot::new(&archive, true)];
+        let ctx = ScanContext::default();
+        let mut files = collect_roots(&roots, &filter, &ctx);
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+
+        let group = &result.duplicates[0];
+        assert_eq!(group.members.len(), 2);
+        let photo = group.members.iter().find(|member| member.path == archive.join("photo.jpg")).unwrap();
+        assert!(photo.reference);
+        assert_eq!(photo.root, 1);
+        assert_eq!(group.members[group.keeper].path, archive.join("photo.jpg"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_replacements_count_as_deduplicated() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("photos")).unwrap();
+        fs::create_dir_all(root.join("backup").join("2020")).unwrap();
+        let keeper = root.join("photos").join("a.jpg");
+        let relative = root.join("backup").join("2020").join("a.jpg");
+        let absolute = root.join("backup").join("a.jpg");
+        for path in [&keeper, &relative, &absolute] {
+            fs::write(path, b"same picture").unwrap();
+        }
+
+        replace_with_symlink(&keeper, &relative, true).unwrap();
+        replace_with_symlink(&keeper, &absolute, false).unwrap();
+        assert_eq!(fs::read_link(&relative).unwrap(), Path::new("../../photos/a.jpg"));
+        assert_eq!(fs::read_link(&absolute).unwrap(), keeper);
+        assert_eq!(fs::read(&relative).unwrap(), b"same picture");
+
+        let options = FilterOptions { symlinks: SymlinkPolicy::Report, ..Default::default() };
+        let filter = FileFilter::new(&options).unwrap();
+        let ctx = ScanContext {
+            dedup_links: Arc::new(HashMap::from([
+                (relative.clone(), keeper.clone()),
+                (absolute.clone(), keeper.clone()),
+            ])),
+            ..Default::default()
+        };
+        let mut files = collect_roots(&[ScanRoot::new(&root, false)], &filter, &ctx);
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+
+        assert_eq!(files.len(), 1);
+        assert!(result.symlinks.is_empty());
+        assert_eq!(result.deduplicated.len(), 2);
+        assert_eq!(result.deduplicated.get(&relative), Some(&keeper));
+    }
+
+    #[test]
+    fn test_excluded_mounts_and_device_summary() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("mnt")).unwrap();
+        fs::write(root.join("mnt").join("remote.bin"), b"payload").unwrap();
+        fs::write(root.join("a.bin"), b"payload").unwrap();
+        fs::write(root.join("b.bin"), b"payload").unwrap();
+
+        let options = FilterOptions {
+            same_file_system: true,
+            excluded_mounts: vec![root.join("mnt")],
+            ..Default::default()
+        };
+        let filter = FileFilter::new(&options).unwrap();
+        let ctx = ScanContext::default();
+        let mut files = collect_files(root, &filter, &ctx);
+        assert_eq!(files.len(), 2);
+
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+        assert_eq!(result.devices.len(), 1);
+        let device = &result.devices[0];
+        assert_eq!((device.files, device.duplicate_files, device.groups), (2, 2, 1));
+        assert_eq!(device.reclaimable, 7);
+    }
+
+    #[test]
+    fn test_unreadable_files_are_reported() {
+        let temp_dir = tempdir().unwrap();
+        let ctx = ScanContext::default();
+        assert!(collect_files(&temp_dir.path().join("missing"), &FileFilter::default(), &ctx).is_empty());
+
+        let a = temp_dir.path().join("a.bin");
+        let b = temp_dir.path().join("b.bin");
+        fs::write(&a, b"same size").unwrap();
+        fs::write(&b, b"same size").unwrap();
+        let mut files = collect_files(temp_dir.path(), &FileFilter::default(), &ctx);
+        fs::remove_file(&b).unwrap();
+
+        let result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+        assert!(result.duplicates.is_empty());
+        assert_eq!(result.problems.len(), 2);
+        assert_eq!(result.problems[0].phase, ScanPhase::Walking);
+        assert_eq!(result.problems[1].kind, ProblemKind::Vanished);
+        assert_eq!(result.problems[1].path.as_deref(), Some(b.as_path()));
+        assert_eq!(result.problems[1].phase, ScanPhase::PartialHash);
+    }
+
+    #[test]
+    fn test_groups_are_ordered_with_stable_ids() {
+        let temp_dir = tempdir().unwrap();
+        let write = |name: &str, content: &[u8]| {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let paths = [
+            write("small-b.txt", b"small"),
+            write("small-a.txt", b"small"),
+            write("small-c.txt", b"small"),
+            write("large-b.txt", b"considerably larger"),
+            write("large-a.txt", b"considerably larger"),
+        ];
+
+        let scan = || {
+            let mut files: Vec<FileInfo> = paths.iter().map(|p| FileInfo::new(p.clone()).unwrap()).collect();
+            find_duplicates(&mut files, &HashOptions::default(), &ScanContext::default()).duplicates
+        };
+        let mut groups = scan();
+        assert_eq!(groups, scan());
+        // 19 wasted bytes beat 2 * 5
+        assert_eq!(groups[0].size, 19);
+        assert_eq!(groups[1].members[0].path, temp_dir.path().join("small-a.txt"));
+
+        sort_groups(&mut groups, GroupSort::MemberCount);
+        assert_eq!(groups[0].members.len(), 3);
+        sort_groups(&mut groups, GroupSort::Path);
+        assert_eq!(groups[0].members[0].path, temp_dir.path().join("large-a.txt"));
+    }
 }
@@ -0,0 +1,404 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use chrono::{Local, NaiveDate, TimeZone};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use crate::ignore_files::{self, IgnoreSources, IgnoreStack};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleKind {
+    /// A file extension such as `jpg`, with or without the leading dot.
+    Extension,
+    /// A glob matched against the file name, or against the full path if
+    /// the pattern contains a separator.
+    Glob,
+    /// A regular expression searched for in the full path.
+    Regex,
+    /// A glob matched against directory names. Excluded directories are
+    /// not descended into at all.
+    Directory,
+}
+
+impl RuleKind {
+    pub const ALL: [RuleKind; 4] = [
+        RuleKind::Extension,
+        RuleKind::Glob,
+        RuleKind::Regex,
+        RuleKind::Directory,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RuleKind::Extension => "Extension",
+            RuleKind::Glob => "Glob",
+            RuleKind::Regex => "Regex",
+            RuleKind::Directory => "Directory",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleAction {
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilterRule {
+    pub action: RuleAction,
+    pub kind: RuleKind,
+    pub pattern: String,
+}
+
+impl FilterRule {
+    pub fn new(action: RuleAction, kind: RuleKind, pattern: impl Into<String>) -> Self {
+        Self { action, kind, pattern: pattern.into() }
+    }
+
+    pub fn exclude(kind: RuleKind, pattern: impl Into<String>) -> Self {
+        Self::new(RuleAction::Exclude, kind, pattern)
+    }
+}
+
+/// Restrictions on file metadata. Ownership and permission constraints are
+/// only checked on Unix.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Constraints {
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Keep files modified on or after the start of this day.
+    pub modified_after: Option<NaiveDate>,
+    /// Keep files modified before the start of this day.
+    pub modified_before: Option<NaiveDate>,
+    /// Numeric user ID that must own the file.
+    pub owner: Option<u32>,
+    /// Numeric group ID that must own the file.
+    pub group: Option<u32>,
+    /// Permission bits that must all be set, e.g. `0o644`.
+    pub mode: Option<u32>,
+    pub skip_hidden: bool,
+}
+
+/// How symbolic links met during traversal are treated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SymlinkPolicy {
+    /// Skip symlinks entirely.
+    #[default]
+    Ignore,
+    /// Walk into linked directories and hash linked files, with loop
+    /// detection. A link to a scanned file is folded into its target.
+    Follow,
+    /// Don't follow, but list links to files separately in the results.
+    Report,
+}
+
+impl SymlinkPolicy {
+    pub const ALL: [SymlinkPolicy; 3] = [
+        SymlinkPolicy::Ignore,
+        SymlinkPolicy::Follow,
+        SymlinkPolicy::Report,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SymlinkPolicy::Ignore => "Ignore",
+            SymlinkPolicy::Follow => "Follow",
+            SymlinkPolicy::Report => "Report separately",
+        }
+    }
+}
+
+/// User-editable filter settings, persisted in the config.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilterOptions {
+    pub rules: Vec<FilterRule>,
+    pub case_sensitive: bool,
+    pub constraints: Constraints,
+    pub ignore_files: IgnoreSources,
+    pub symlinks: SymlinkPolicy,
+    /// Don't descend into directories on a different device than the root.
+    pub same_file_system: bool,
+    /// Mount points, or any other directories, that are never entered.
+    pub excluded_mounts: Vec<PathBuf>,
+}
+
+enum Matcher {
+    Extension(String),
+    Name(GlobMatcher),
+    Path(GlobMatcher),
+    Regex(Regex),
+    Directory(GlobMatcher),
+}
+
+struct CompiledRule {
+    action: RuleAction,
+    matcher: Matcher,
+}
+
+/// Compiled form of [`FilterOptions`]. A file is accepted if no exclude
+/// rule matches it and, when there are include rules, at least one does.
+#[derive(Clone, Default)]
+pub struct FileFilter {
+    rules: Arc<Vec<CompiledRule>>,
+    options: FilterOptions,
+    global_ignore: Option<PathBuf>,
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+}
+
+impl FileFilter {
+    pub fn new(options: &FilterOptions) -> io::Result<Self> {
+        let rules = options.rules.iter()
+            .map(|rule| compile(rule, options.case_sensitive))
+            .collect::<io::Result<Vec<_>>>()?;
+        let constraints = &options.constraints;
+        if let (Some(min), Some(max)) = (constraints.min_size, constraints.max_size) {
+            if min > max {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "minimum size exceeds maximum size"));
+            }
+        }
+        Ok(Self {
+            rules: Arc::new(rules),
+            modified_after: constraints.modified_after.map(start_of_day),
+            modified_before: constraints.modified_before.map(start_of_day),
+            options: options.clone(),
+            global_ignore: ignore_files::global_path(),
+        })
+    }
+
+    /// The settings this filter was built from.
+    pub fn options(&self) -> &FilterOptions {
+        &self.options
+    }
+
+    /// Fresh ignore-file state for walking `root`.
+    pub fn ignore_stack(&self, root: &Path) -> IgnoreStack {
+        IgnoreStack::new(&self.options.ignore_files, self.global_ignore.as_deref(), root)
+    }
+
+    /// Whether a directory may be descended into.
+    pub fn enters_dir(&self, path: &Path) -> bool {
+        if self.options.excluded_mounts.iter().any(|mount| mount == path) {
+            return false;
+        }
+        let Some(name) = path.file_name() else {
+            return true;
+        };
+        if self.options.constraints.skip_hidden && is_hidden(name) {
+            return false;
+        }
+        !self.rules.iter().any(|rule| {
+            rule.action == RuleAction::Exclude
+                && matches!(&rule.matcher, Matcher::Directory(glob) if glob.is_match(name))
+        })
+    }
+
+    /// Checks the metadata constraints. Files whose modification time is
+    /// unknown fail any date constraint.
+    pub fn accepts_metadata(&self, metadata: &fs::Metadata) -> bool {
+        let c = &self.options.constraints;
+        let size = metadata.len();
+        if c.min_size.is_some_and(|min| size < min) || c.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+        if self.modified_after.is_some() || self.modified_before.is_some() {
+            let Ok(modified) = metadata.modified() else {
+                return false;
+            };
+            if self.modified_after.is_some_and(|after| modified < after)
+                || self.modified_before.is_some_and(|before| modified >= before)
+            {
+                return false;
+            }
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if c.owner.is_some_and(|uid| metadata.uid() != uid)
+                || c.group.is_some_and(|gid| metadata.gid() != gid)
+                || c.mode.is_some_and(|mode| metadata.mode() & mode != mode)
+            {
+                return false;
+            }
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::MetadataExt;
+            const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+            if c.skip_hidden && metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether a file found while scanning `root` is accepted. Directory
+    /// rules only look at the directories below `root`.
+    pub fn accepts(&self, path: &Path, root: &Path) -> bool {
+        if self.options.constraints.skip_hidden && path.file_name().is_some_and(is_hidden) {
+            return false;
+        }
+        let mut has_include = false;
+        let mut included = false;
+        for rule in self.rules.iter() {
+            let matched = self.is_match(&rule.matcher, path, root);
+            match rule.action {
+                RuleAction::Exclude if matched => return false,
+                RuleAction::Exclude => {}
+                RuleAction::Include => {
+                    has_include = true;
+                    included |= matched;
+                }
+            }
+        }
+        !has_include || included
+    }
+
+    fn is_match(&self, matcher: &Matcher, path: &Path, root: &Path) -> bool {
+        match matcher {
+            Matcher::Extension(ext) => path.extension()
+                .and_then(OsStr::to_str)
+                .is_some_and(|actual| if self.options.case_sensitive {
+                    actual == ext
+                } else {
+                    actual.eq_ignore_ascii_case(ext)
+                }),
+            Matcher::Name(glob) => path.file_name().is_some_and(|name| glob.is_match(name)),
+            Matcher::Path(glob) => glob.is_match(path),
+            Matcher::Regex(regex) => regex.is_match(&path.to_string_lossy()),
+            Matcher::Directory(glob) => path.parent()
+                .map(|parent| parent.strip_prefix(root).unwrap_or(parent))
+                .is_some_and(|parent| parent.iter().any(|name| glob.is_match(name))),
+        }
+    }
+}
+
+fn is_hidden(name: &OsStr) -> bool {
+    name.as_encoded_bytes().first() == Some(&b'.')
+}
+
+fn start_of_day(date: NaiveDate) -> SystemTime {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+    Local.from_local_datetime(&midnight)
+        .earliest()
+        .map(SystemTime::from)
+        .unwrap_or_else(|| SystemTime::from(midnight.and_utc()))
+}
+
+fn compile(rule: &FilterRule, case_sensitive: bool) -> io::Result<CompiledRule> {
+    let pattern = rule.pattern.trim();
+    let glob = |pattern: &str| {
+        GlobBuilder::new(pattern)
+            .case_insensitive(!case_sensitive)
+            .literal_separator(true)
+            .build()
+            .map(|glob| glob.compile_matcher())
+            .map_err(|e| invalid(rule, e))
+    };
+    let matcher = match rule.kind {
+        RuleKind::Extension => Matcher::Extension(
+            pattern.trim_start_matches('*').trim_start_matches('.').to_string()
+        ),
+        RuleKind::Glob if pattern.contains('/') => Matcher::Path(glob(pattern)?),
+        RuleKind::Glob => Matcher::Name(glob(pattern)?),
+        RuleKind::Regex => Matcher::Regex(
+            RegexBuilder::new(pattern)
+                .case_insensitive(!case_sensitive)
+                .build()
+                .map_err(|e| invalid(rule, e))?
+        ),
+        RuleKind::Directory => Matcher::Directory(glob(pattern.trim_end_matches('/'))?),
+    };
+    Ok(CompiledRule { action: rule.action, matcher })
+}
+
+fn invalid(rule: &FilterRule, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid {} rule '{}': {}", rule.kind.label().to_lowercase(), rule.pattern, e),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(rules: Vec<FilterRule>) -> FileFilter {
+        FileFilter::new(&FilterOptions { rules, ..Default::default() }).unwrap()
+    }
+
+    #[test]
+    fn test_rules_match_case_insensitively() {
+        let filter = filter(vec![
+            FilterRule::exclude(RuleKind::Extension, "jpg"),
+            FilterRule::exclude(RuleKind::Glob, "*.tmp.*"),
+            FilterRule::exclude(RuleKind::Regex, r"/cache/\d+$"),
+        ]);
+
+        assert!(!filter.accepts(Path::new("/photos/IMG_0001.JPG"), Path::new("/")));
+        assert!(!filter.accepts(Path::new("/work/report.TMP.docx"), Path::new("/")));
+        assert!(!filter.accepts(Path::new("/var/Cache/1234"), Path::new("/")));
+        assert!(filter.accepts(Path::new("/photos/IMG_0001.png"), Path::new("/")));
+    }
+
+    #[test]
+    fn test_include_and_directory_rules() {
+        let filter = filter(vec![
+            FilterRule::new(RuleAction::Include, RuleKind::Glob, "**/photos/**"),
+            FilterRule::exclude(RuleKind::Directory, "node_modules"),
+        ]);
+
+        assert!(filter.accepts(Path::new("/home/me/photos/a.png"), Path::new("/")));
+        assert!(!filter.accepts(Path::new("/home/me/music/a.mp3"), Path::new("/")));
+        assert!(!filter.accepts(Path::new("/home/me/photos/node_modules/a.png"), Path::new("/")));
+        // Directories the scan root itself sits in don't count
+        assert!(filter.accepts(Path::new("/src/node_modules/photos/a.png"), Path::new("/src/node_modules")));
+        assert!(!filter.enters_dir(Path::new("/src/Node_Modules")));
+        assert!(filter.enters_dir(Path::new("/src/lib")));
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_reported() {
+        let options = FilterOptions {
+            rules: vec![FilterRule::exclude(RuleKind::Regex, "(unclosed")],
+            ..Default::default()
+        };
+        let err = FileFilter::new(&options).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_metadata_constraints() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let small = temp_dir.path().join("small.txt");
+        let large = temp_dir.path().join("large.txt");
+        fs::write(&small, b"tiny").unwrap();
+        fs::write(&large, vec![0u8; 4096]).unwrap();
+
+        let mut options = FilterOptions::default();
+        options.constraints.min_size = Some(100);
+        options.constraints.skip_hidden = true;
+        let filter = FileFilter::new(&options).unwrap();
+        assert!(!filter.accepts_metadata(&fs::metadata(&small).unwrap()));
+        assert!(filter.accepts_metadata(&fs::metadata(&large).unwrap()));
+        assert!(!filter.accepts(&temp_dir.path().join(".hidden"), Path::new("/")));
+        assert!(!filter.enters_dir(&temp_dir.path().join(".git")));
+
+        // Everything was written today, so nothing predates today
+        options.constraints.modified_before = Some(Local::now().date_naive());
+        let filter = FileFilter::new(&options).unwrap();
+        assert!(!filter.accepts_metadata(&fs::metadata(&large).unwrap()));
+
+        options.constraints.max_size = Some(10);
+        assert!(FileFilter::new(&options).is_err());
+    }
+}
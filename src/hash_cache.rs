@@ -0,0 +1,242 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::file_utils::{FileInfo, HashOptions};
+
+/// On-disk store of previously computed hashes. An entry is only reused
+/// while the file's path, size, modification time and inode are unchanged.
+pub struct HashCache {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl HashCache {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("dupfi").join("hash_cache.sqlite"))
+    }
+
+    pub fn open_default() -> io::Result<Self> {
+        match Self::default_path() {
+            Some(path) => Self::open(&path),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no data directory available")),
+        }
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path).map_err(to_io)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS hashes (
+                path TEXT NOT NULL,
+                algorithm TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                mtime INTEGER NOT NULL,
+                inode INTEGER NOT NULL,
+                partial_size INTEGER NOT NULL,
+                head_hash BLOB,
+                tail_hash BLOB,
+                full_hash BLOB,
+                PRIMARY KEY (path, algorithm)
+            );",
+        ).map_err(to_io)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Fills in cached stage hashes for `files`. Partial hashes are only
+    /// reused if they were computed with the same partial size.
+    pub fn lookup<'a>(&self, files: impl IntoIterator<Item = &'a mut FileInfo>, options: &HashOptions) -> io::Result<CacheStats> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached(
+            "SELECT partial_size, head_hash, tail_hash, full_hash FROM hashes
+             WHERE path = ?1 AND algorithm = ?2 AND size = ?3 AND mtime = ?4 AND inode = ?5",
+        ).map_err(to_io)?;
+
+        let mut stats = CacheStats::default();
+        for file in files {
+            let row = stmt.query_row(
+                params![path_key(&file.path), algorithm_key(options), file.size as i64, mtime_key(file.modified), file.inode as i64],
+                |row| Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Option<Vec<u8>>>(1)?,
+                    row.get::<_, Option<Vec<u8>>>(2)?,
+                    row.get::<_, Option<Vec<u8>>>(3)?,
+                )),
+            ).optional().map_err(to_io)?;
+
+            match row {
+                Some((partial_size, head, tail, full)) => {
+                    if partial_size == options.partial_size as i64 {
+                        file.head_hash = file.head_hash.take().or(head);
+                        file.tail_hash = file.tail_hash.take().or(tail);
+                    }
+                    file.hash = file.hash.take().or(full);
+                    stats.hits += 1;
+                }
+                None => stats.misses += 1,
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Records every stage hash known for `files`, replacing older entries.
+    pub fn store<'a>(&self, files: impl IntoIterator<Item = &'a FileInfo>, options: &HashOptions) -> io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(to_io)?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO hashes
+                 (path, algorithm, size, mtime, inode, partial_size, head_hash, tail_hash, full_hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            ).map_err(to_io)?;
+            for file in files {
+                if file.head_hash.is_none() && file.hash.is_none() {
+                    continue;
+                }
+                stmt.execute(params![
+                    path_key(&file.path),
+                    algorithm_key(options),
+                    file.size as i64,
+                    mtime_key(file.modified),
+                    file.inode as i64,
+                    options.partial_size as i64,
+                    file.head_hash,
+                    file.tail_hash,
+                    file.hash,
+                ]).map_err(to_io)?;
+            }
+        }
+        tx.commit().map_err(to_io)
+    }
+
+    pub fn entry_count(&self) -> io::Result<u64> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM hashes", [], |row| row.get::<_, i64>(0))
+            .map(|count| count as u64)
+            .map_err(to_io)
+    }
+
+    /// Removes entries for files that no longer exist or have changed since
+    /// they were hashed. Returns the number of removed entries.
+    pub fn prune(&self) -> io::Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let entries: Vec<(String, i64, i64, i64)> = {
+            let mut stmt = conn.prepare("SELECT DISTINCT path, size, mtime, inode FROM hashes").map_err(to_io)?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+                .map_err(to_io)?;
+            rows.collect::<Result<_, _>>().map_err(to_io)?
+        };
+
+        let mut removed = 0;
+        for (path, size, mtime, inode) in entries {
+            let unchanged = FileInfo::new(PathBuf::from(&path))
+                .map(|file| file.size as i64 == size && mtime_key(file.modified) == mtime && file.inode as i64 == inode)
+                .unwrap_or(false);
+            if !unchanged {
+                removed += conn.execute("DELETE FROM hashes WHERE path = ?1", params![path]).map_err(to_io)?;
+            }
+        }
+        Ok(removed)
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM hashes", []).map(|_| ()).map_err(to_io)
+    }
+}
+
+fn path_key(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn algorithm_key(options: &HashOptions) -> String {
+    format!("{:?}", options.algorithm)
+}
+
+fn mtime_key(modified: Option<SystemTime>) -> i64 {
+    match modified.map(|time| time.duration_since(UNIX_EPOCH)) {
+        Some(Ok(since)) => since.as_nanos() as i64,
+        Some(Err(before)) => -(before.duration().as_nanos() as i64),
+        None => 0,
+    }
+}
+
+fn to_io(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+    use crate::hasher::HashAlgorithm;
+
+    #[test]
+    fn test_lookup_hits_until_file_changes() {
+        let temp_dir = tempdir().unwrap();
+        let cache = HashCache::open(&temp_dir.path().join("cache.sqlite")).unwrap();
+        let options = HashOptions::default();
+
+        let path = temp_dir.path().join("file.txt");
+        fs::write(&path, b"cached content").unwrap();
+        let mut file = FileInfo::new(path.clone()).unwrap();
+        file.hash = Some(vec![1, 2, 3]);
+        cache.store([&file], &options).unwrap();
+
+        let mut fresh = FileInfo::new(path.clone()).unwrap();
+        let stats = cache.lookup([&mut fresh], &options).unwrap();
+        assert_eq!(stats, CacheStats { hits: 1, misses: 0 });
+        assert_eq!(fresh.hash, Some(vec![1, 2, 3]));
+
+        // Another algorithm never sees the entry
+        let blake = HashOptions { algorithm: HashAlgorithm::Blake3, ..Default::default() };
+        let mut other = FileInfo::new(path.clone()).unwrap();
+        assert_eq!(cache.lookup([&mut other], &blake).unwrap().misses, 1);
+
+        fs::write(&path, b"changed content, different size").unwrap();
+        let mut changed = FileInfo::new(path).unwrap();
+        assert_eq!(cache.lookup([&mut changed], &options).unwrap().misses, 1);
+        assert!(changed.hash.is_none());
+    }
+
+    #[test]
+    fn test_prune_removes_missing_files() {
+        let temp_dir = tempdir().unwrap();
+        let cache = HashCache::open(&temp_dir.path().join("cache.sqlite")).unwrap();
+        let options = HashOptions::default();
+
+        let mut files = Vec::new();
+        for name in ["keep.txt", "gone.txt"] {
+            let path = temp_dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            let mut file = FileInfo::new(path).unwrap();
+            file.hash = Some(name.as_bytes().to_vec());
+            files.push(file);
+        }
+        cache.store(&files, &options).unwrap();
+        assert_eq!(cache.entry_count().unwrap(), 2);
+
+        fs::remove_file(temp_dir.path().join("gone.txt")).unwrap();
+        assert_eq!(cache.prune().unwrap(), 1);
+        assert_eq!(cache.entry_count().unwrap(), 1);
+    }
+}
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Incremental content hasher; file data is fed in chunks and the digest
+/// bytes are only used as an opaque grouping key.
+pub trait ContentHasher: Send {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self: Box<Self>) -> Vec<u8>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+    Xxh3,
+    Crc32,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 4] = [
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Blake3,
+        HashAlgorithm::Xxh3,
+        HashAlgorithm::Crc32,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "SHA-256",
+            HashAlgorithm::Blake3 => "BLAKE3",
+            HashAlgorithm::Xxh3 => "xxHash3-128",
+            HashAlgorithm::Crc32 => "CRC32 (quick check)",
+        }
+    }
+
+    pub fn is_cryptographic(&self) -> bool {
+        matches!(self, HashAlgorithm::Sha256 | HashAlgorithm::Blake3)
+    }
+
+    pub fn hasher(&self) -> Box<dyn ContentHasher> {
+        match self {
+            HashAlgorithm::Sha256 => Box::new(Sha256::new()),
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            HashAlgorithm::Xxh3 => Box::new(Xxh3::new()),
+            HashAlgorithm::Crc32 => Box::new(crc32fast::Hasher::new()),
+        }
+    }
+}
+
+impl ContentHasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        Digest::finalize(*self).to_vec()
+    }
+}
+
+impl ContentHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        blake3::Hasher::finalize(&self).as_bytes().to_vec()
+    }
+}
+
+impl ContentHasher for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        Xxh3::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        self.digest128().to_be_bytes().to_vec()
+    }
+}
+
+impl ContentHasher for crc32fast::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        crc32fast::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        crc32fast::Hasher::finalize(*self).to_be_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(algorithm: HashAlgorithm, chunks: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = algorithm.hasher();
+        for chunk in chunks {
+            hasher.update(chunk);
+        }
+        hasher.finalize()
+    }
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(
+            digest(HashAlgorithm::Sha256, &[b"abc"]),
+            Sha256::digest(b"abc").to_vec()
+        );
+        assert_eq!(
+            digest(HashAlgorithm::Blake3, &[b""]),
+            blake3::hash(b"").as_bytes().to_vec()
+        );
+        assert_eq!(digest(HashAlgorithm::Crc32, &[b"123456789"]), vec![0xCB, 0xF4, 0x39, 0x26]);
+        assert_eq!(digest(HashAlgorithm::Xxh3, &[b"abc"]).len(), 16);
+    }
+
+    #[test]
+    fn test_chunked_updates_match_single_update() {
+        for algorithm in HashAlgorithm::ALL {
+            assert_eq!(
+                digest(algorithm, &[b"hello ", b"wor", b"ld"]),
+                digest(algorithm, &[b"hello world"]),
+                "{} differs when fed in chunks",
+                algorithm.label()
+            );
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::path::{Path, PathBuf};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Project-specific ignore file, read in every scanned directory.
+pub const DUPFI_IGNORE: &str = ".dupfiignore";
+
+/// Which gitignore-style files are honored during traversal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IgnoreSources {
+    pub gitignore: bool,
+    pub dot_ignore: bool,
+    pub dupfiignore: bool,
+    /// The global ignore file in the config directory.
+    pub global: bool,
+}
+
+impl Default for IgnoreSources {
+    fn default() -> Self {
+        Self {
+            gitignore: false,
+            dot_ignore: false,
+            dupfiignore: true,
+            global: true,
+        }
+    }
+}
+
+impl IgnoreSources {
+    /// Per-directory file names, lowest precedence first.
+    fn file_names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.gitignore {
+            names.push(".gitignore");
+        }
+        if self.dot_ignore {
+            names.push(".ignore");
+        }
+        if self.dupfiignore {
+            names.push(DUPFI_IGNORE);
+        }
+        names
+    }
+}
+
+pub fn global_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("dupfi").join("ignore"))
+}
+
+/// Ignore matchers of the directories leading to the current walk entry.
+/// Entries must be fed in walk order; deeper and later rules win.
+pub struct IgnoreStack {
+    names: Vec<&'static str>,
+    global: Option<Gitignore>,
+    stack: Vec<(usize, Gitignore)>,
+}
+
+impl IgnoreStack {
+    /// `global` patterns are anchored at `root`, the directory being walked.
+    pub fn new(sources: &IgnoreSources, global: Option<&Path>, root: &Path) -> Self {
+        let global = global
+            .filter(|path| sources.global && path.is_file())
+            .and_then(|path| build(root, &[path.to_path_buf()]));
+        Self {
+            names: sources.file_names(),
+            global,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Whether the entry is ignored. Directories that are kept have their
+    /// own ignore files loaded for the entries below them.
+    pub fn is_ignored(&mut self, path: &Path, depth: usize, is_dir: bool) -> bool {
+        while self.stack.last().is_some_and(|(d, _)| *d >= depth) {
+            self.stack.pop();
+        }
+
+        let ignored = depth > 0 && self.stack.iter()
+            .rev()
+            .map(|(_, matcher)| matcher)
+            .chain(self.global.as_ref())
+            .map(|matcher| matcher.matched(path, is_dir))
+            .find(|m| !m.is_none())
+            .is_some_and(|m| m.is_ignore());
+
+        if is_dir && !ignored && !self.names.is_empty() {
+            let files: Vec<PathBuf> = self.names.iter().map(|name| path.join(name)).collect();
+            if let Some(matcher) = build(path, &files) {
+                self.stack.push((depth, matcher));
+            }
+        }
+        ignored
+    }
+}
+
+fn build(root: &Path, files: &[PathBuf]) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for file in files.iter().filter(|file| file.is_file()) {
+        // Unparseable lines are skipped, the rest of the file still applies
+        let _ = builder.add(file);
+    }
+    builder.build().ok().filter(|matcher| !matcher.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_nested_ignore_files() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("app").join("target")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join("app").join(DUPFI_IGNORE), "!keep.log\n").unwrap();
+
+        let sources = IgnoreSources { gitignore: true, ..Default::default() };
+        let mut stack = IgnoreStack::new(&sources, None, root);
+        assert!(!stack.is_ignored(root, 0, true));
+        assert!(stack.is_ignored(&root.join("debug.log"), 1, false));
+        assert!(!stack.is_ignored(&root.join("app"), 1, true));
+        assert!(stack.is_ignored(&root.join("app").join("target"), 2, true));
+        assert!(!stack.is_ignored(&root.join("app").join("keep.log"), 2, false));
+        assert!(stack.is_ignored(&root.join("app").join("other.log"), 2, false));
+        // Leaving `app` drops its rules again
+        assert!(stack.is_ignored(&root.join("keep.log"), 1, false));
+    }
+
+    #[test]
+    fn test_disabled_sources_are_not_read() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join(".gitignore"), "*.bin\n").unwrap();
+        let global = root.join("global-ignore");
+        fs::write(&global, "*.iso\n").unwrap();
+
+        let sources = IgnoreSources::default();
+        let mut stack = IgnoreStack::new(&sources, Some(&global), root);
+        stack.is_ignored(root, 0, true);
+        assert!(!stack.is_ignored(&root.join("image.bin"), 1, false));
+        assert!(stack.is_ignored(&root.join("disk.iso"), 1, false));
+
+        let sources = IgnoreSources { global: false, ..Default::default() };
+        let mut stack = IgnoreStack::new(&sources, Some(&global), root);
+        stack.is_ignored(root, 0, true);
+        assert!(!stack.is_ignored(&root.join("disk.iso"), 1, false));
+    }
+}
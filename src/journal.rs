@@ -0,0 +1,651 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use crate::{
+    file_utils::{move_file, same_file, Ownership},
+    plan::{ActionPlan, Operation, PlanOutcome, PlanStep},
+    trash,
+};
+
+const JOURNAL_FILE: &str = "journal.json";
+const QUARANTINE_DIR: &str = "quarantine";
+/// Extension of the log a batch appends its entries to while it runs.
+const PENDING_EXT: &str = "pending";
+/// Quarantine kept at the top of other file systems, followed by the uid.
+const DEVICE_QUARANTINE_PREFIX: &str = ".dupfi-quarantine-";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub step: PlanStep,
+    /// Where the replaced or deleted file was moved to, the quarantine or
+    /// the trash.
+    pub quarantined: Option<PathBuf>,
+    /// The keeper's mode and owner before a hard link gave it the
+    /// duplicate's.
+    #[serde(default)]
+    pub keeper_ownership: Option<Ownership>,
+    /// Where the symbolic link that replaced the file points.
+    #[serde(default)]
+    pub link_target: Option<PathBuf>,
+    pub undone: bool,
+}
+
+impl JournalEntry {
+    fn in_quarantine(&self) -> bool {
+        self.quarantined.is_some() && self.step.operation != Operation::Trash
+    }
+}
+
+/// The steps of one executed plan that succeeded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalBatch {
+    pub id: u64,
+    pub executed_at: DateTime<Local>,
+    pub entries: Vec<JournalEntry>,
+    /// The quarantined files of this batch are gone.
+    pub purged: bool,
+}
+
+impl JournalBatch {
+    pub fn reclaimed(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.step.reclaimed()).sum()
+    }
+
+    pub fn is_undone(&self) -> bool {
+        self.entries.iter().all(|entry| entry.undone)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.is_undone() && (!self.purged || !self.entries.iter().any(JournalEntry::in_quarantine))
+    }
+}
+
+/// Persistent record of every executed plan. Files a plan deletes or
+/// replaces are kept in a quarantine directory until it is purged, so
+/// each batch can be undone.
+pub struct Journal {
+    dir: PathBuf,
+    batches: Vec<JournalBatch>,
+}
+
+impl Journal {
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("dupfi").join("journal"))
+    }
+
+    pub fn open_default() -> io::Result<Self> {
+        match Self::default_dir() {
+            Some(dir) => Self::open(&dir),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no data directory available")),
+        }
+    }
+
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let batches = match fs::read_to_string(dir.join(JOURNAL_FILE)) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut journal = Self {
+            dir: dir.to_path_buf(),
+            batches,
+        };
+        journal.recover()?;
+        Ok(journal)
+    }
+
+    /// Adds the batches that were cut short, say by a crash, from their
+    /// logs. Their files are in quarantine and can still be restored.
+    fn recover(&mut self) -> io::Result<()> {
+        let mut logs = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == PENDING_EXT) {
+                logs.push(path);
+            }
+        }
+        if logs.is_empty() {
+            return Ok(());
+        }
+
+        for log in &logs {
+            let contents = fs::read_to_string(log)?;
+            let mut lines = contents.lines();
+            let Some(Ok(mut batch)) = lines.next().map(serde_json::from_str::<JournalBatch>) else {
+                continue;
+            };
+            // A line cut off mid-write ends the log
+            batch.entries.extend(lines.map_while(|line| serde_json::from_str(line).ok()));
+            if !batch.entries.is_empty() && !self.batches.iter().any(|known| known.id == batch.id) {
+                self.batches.push(batch);
+            }
+        }
+        self.batches.sort_by_key(|batch| batch.id);
+        self.save()?;
+        for log in &logs {
+            fs::remove_file(log)?;
+        }
+        Ok(())
+    }
+
+    pub fn batches(&self) -> &[JournalBatch] {
+        &self.batches
+    }
+
+    /// Bytes held in quarantine by batches that were not undone.
+    pub fn quarantine_size(&self) -> u64 {
+        self.batches.iter()
+            .filter(|batch| !batch.purged)
+            .flat_map(|batch| &batch.entries)
+            .filter(|entry| entry.in_quarantine() && !entry.undone)
+            .map(|entry| entry.step.size)
+            .sum()
+    }
+
+    /// Symbolic links that replaced duplicates and were not undone, with
+    /// the keeper each points to.
+    pub fn symlinks(&self) -> HashMap<PathBuf, PathBuf> {
+        self.batches.iter()
+            .flat_map(|batch| &batch.entries)
+            .filter(|entry| !entry.undone)
+            .filter_map(|entry| match &entry.step.operation {
+                Operation::Symlink { keeper, .. } => Some((entry.step.path.clone(), keeper.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Runs the plan's steps in order, continuing past failures, and
+    /// records the ones that succeeded as a new batch. Each one is logged
+    /// as soon as it is done, so an interrupted batch can still be undone.
+    pub fn execute(&mut self, plan: &ActionPlan) -> io::Result<PlanOutcome> {
+        let (id, quarantine) = self.create_quarantine()?;
+        let mut quarantines = Quarantines::new(id, &quarantine);
+        let mut batch = JournalBatch {
+            id,
+            executed_at: Local::now(),
+            entries: Vec::new(),
+            purged: false,
+        };
+        let log_path = self.dir.join(format!("{}.{}", id, PENDING_EXT));
+        let mut log = fs::OpenOptions::new().append(true).create_new(true).open(&log_path)?;
+        writeln!(log, "{}", serde_json::to_string(&batch)?)?;
+
+        let mut outcome = PlanOutcome::default();
+        for (idx, step) in plan.steps.iter().enumerate() {
+            let quarantine = match &step.operation {
+                Operation::Delete | Operation::Hardlink { .. } | Operation::Symlink { .. } => {
+                    match quarantines.for_file(&step.path) {
+                        Ok(dir) => dir.join(idx.to_string()),
+                        Err(e) => {
+                            outcome.errors.push(format!("{}: {}", step.path.display(), e));
+                            continue;
+                        }
+                    }
+                }
+                _ => quarantine.join(idx.to_string()),
+            };
+            let keeper_ownership = match &step.operation {
+                Operation::Hardlink { keeper, preserve_permissions: true } => {
+                    fs::metadata(keeper).ok().map(|metadata| Ownership::of(&metadata))
+                }
+                _ => None,
+            };
+            match step.run(&quarantine) {
+                Ok(quarantined) => {
+                    outcome.done.push(step.path.clone());
+                    outcome.reclaimed += step.reclaimed();
+                    let link_target = match step.operation {
+                        Operation::Symlink { .. } => fs::read_link(&step.path).ok(),
+                        _ => None,
+                    };
+                    let entry = JournalEntry { step: step.clone(), quarantined, keeper_ownership, link_target, undone: false };
+                    if let Err(e) = writeln!(log, "{}", serde_json::to_string(&entry)?) {
+                        outcome.errors.push(format!("{}: not logged: {}", step.path.display(), e));
+                    }
+                    batch.entries.push(entry);
+                }
+                Err(e) => outcome.errors.push(format!("{}: {}", step.path.display(), e)),
+            }
+        }
+        drop(log);
+        for dir in quarantines.devices.values() {
+            let _ = fs::remove_dir(dir);
+        }
+
+        if batch.entries.is_empty() {
+            let _ = fs::remove_dir(&quarantine);
+            let _ = fs::remove_file(&log_path);
+            return Ok(outcome);
+        }
+        self.batches.push(batch);
+        match self.save() {
+            // The log is kept otherwise, the batch is recovered from it
+            Ok(()) => fs::remove_file(&log_path)?,
+            Err(e) => outcome.errors.push(format!("journal not saved: {}", e)),
+        }
+        Ok(outcome)
+    }
+
+    /// A quarantine directory of its own for the next batch. Existing ones
+    /// are never reused, they may hold files of an interrupted batch.
+    fn create_quarantine(&self) -> io::Result<(u64, PathBuf)> {
+        let root = self.dir.join(QUARANTINE_DIR);
+        fs::create_dir_all(&root)?;
+        let mut id = self.batches.iter().map(|batch| batch.id + 1).max().unwrap_or(1);
+        loop {
+            let dir = root.join(id.to_string());
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok((id, dir)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => id += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reverts a batch, last step first. Steps that fail stay recorded and
+    /// can be retried.
+    pub fn undo(&mut self, id: u64) -> io::Result<Vec<String>> {
+        let batch = self.batches.iter_mut()
+            .find(|batch| batch.id == id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such batch in the journal"))?;
+        if !batch.can_undo() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the batch can no longer be undone"));
+        }
+
+        let mut errors = Vec::new();
+        for entry in batch.entries.iter_mut().rev().filter(|entry| !entry.undone) {
+            match restore(entry) {
+                Ok(()) => entry.undone = true,
+                Err(e) => errors.push(format!("{}: {}", entry.step.path.display(), e)),
+            }
+        }
+        let _ = fs::remove_dir(self.dir.join(QUARANTINE_DIR).join(id.to_string()));
+        for quarantined in batch.entries.iter().filter(|entry| entry.in_quarantine()).filter_map(|entry| entry.quarantined.as_ref()) {
+            if let Some(dir) = quarantined.parent() {
+                let _ = fs::remove_dir(dir);
+            }
+        }
+        self.save()?;
+        Ok(errors)
+    }
+
+    /// Permanently deletes all quarantined files. Batches that needed them
+    /// can no longer be undone.
+    pub fn purge(&mut self) -> io::Result<()> {
+        match fs::remove_dir_all(self.dir.join(QUARANTINE_DIR)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        for batch in &mut self.batches {
+            if !batch.purged {
+                // Quarantines on other file systems
+                for entry in batch.entries.iter().filter(|entry| entry.in_quarantine() && !entry.undone) {
+                    if let Some(quarantined) = &entry.quarantined {
+                        let _ = fs::remove_file(quarantined);
+                        if let Some(dir) = quarantined.parent() {
+                            let _ = fs::remove_dir(dir);
+                        }
+                    }
+                }
+            }
+            batch.purged = true;
+        }
+        self.save()
+    }
+
+    /// Writes a temporary file and renames it over the journal, so a crash
+    /// while saving leaves the previous journal intact.
+    fn save(&self) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.batches)?;
+        let temp = self.dir.join(format!("{}.tmp", JOURNAL_FILE));
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, self.dir.join(JOURNAL_FILE))
+    }
+}
+
+/// The quarantine directories of one batch. Files are quarantined on their
+/// own file system, at its top like a trash directory, so that removing
+/// them never copies them to the disk holding the journal.
+struct Quarantines {
+    id: u64,
+    home: PathBuf,
+    home_device: Option<u64>,
+    devices: HashMap<u64, PathBuf>,
+}
+
+impl Quarantines {
+    fn new(id: u64, home: &Path) -> Self {
+        Self {
+            id,
+            home: home.to_path_buf(),
+            home_device: device_of(home),
+            devices: HashMap::new(),
+        }
+    }
+
+    fn for_file(&mut self, path: &Path) -> io::Result<&Path> {
+        let device = match device_of(path) {
+            Some(device) if Some(device) != self.home_device => device,
+            _ => return Ok(&self.home),
+        };
+        let dir = match self.devices.entry(device) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let dir = device_quarantine(path, device, self.id).map_err(|e| io::Error::new(
+                    e.kind(),
+                    format!("no quarantine on this file system, nothing was changed: {}", e),
+                ))?;
+                entry.insert(dir)
+            }
+        };
+        Ok(dir)
+    }
+}
+
+#[cfg(unix)]
+fn device_of(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::symlink_metadata(path).ok().map(|metadata| metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(_path: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(unix)]
+fn device_quarantine(path: &Path, device: u64, id: u64) -> io::Result<PathBuf> {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let path = parent.canonicalize()?.join(path.file_name().unwrap_or_default());
+    batch_dir(&trash::mount_top(&path, device), id)
+}
+
+#[cfg(not(unix))]
+fn device_quarantine(_path: &Path, _device: u64, _id: u64) -> io::Result<PathBuf> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "quarantines on other file systems need Unix"))
+}
+
+/// A new directory for batch `id` in the user's quarantine below `top`.
+/// The quarantine must be a private directory of this user.
+#[cfg(unix)]
+fn batch_dir(top: &Path, id: u64) -> io::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let uid = unsafe { libc::getuid() };
+    let root = top.join(format!("{}{}", DEVICE_QUARANTINE_PREFIX, uid));
+    match fs::DirBuilder::new().mode(0o700).create(&root) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let metadata = fs::symlink_metadata(&root)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is not a private directory", root.display())));
+    }
+
+    let mut attempt = 1;
+    loop {
+        let dir = match attempt {
+            1 => root.join(id.to_string()),
+            _ => root.join(format!("{}.{}", id, attempt)),
+        };
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn restore(entry: &JournalEntry) -> io::Result<()> {
+    let path = &entry.step.path;
+    let occupied = fs::symlink_metadata(path).is_ok();
+    match (&entry.step.operation, &entry.quarantined) {
+        (Operation::Move { destination }, _) => {
+            if occupied {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the original location is taken"));
+            }
+            move_file(destination, path)
+        }
+        (Operation::Delete | Operation::Trash, Some(quarantined)) => {
+            if occupied {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the original location is taken"));
+            }
+            if entry.step.operation == Operation::Trash {
+                trash::restore(quarantined, path)
+            } else {
+                move_file(quarantined, path)
+            }
+        }
+        (Operation::Hardlink { .. } | Operation::Symlink { .. }, Some(quarantined)) => {
+            // The link that replaced the file goes, the original comes back.
+            // Removing it first keeps a copying fallback from writing
+            // through the link into the keeper.
+            if occupied {
+                if !is_own_link(entry) {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the link was replaced since, the original location is taken"));
+                }
+                fs::remove_file(path)?;
+            }
+            move_file(quarantined, path)?;
+            match (&entry.step.operation, &entry.keeper_ownership) {
+                (Operation::Hardlink { keeper, .. }, Some(ownership)) => ownership.apply(keeper),
+                _ => Ok(()),
+            }
+        }
+        // Sharing extents changed nothing visible, there is nothing to restore
+        (Operation::Reflink { .. }, _) => Ok(()),
+        (_, None) => Err(io::Error::new(io::ErrorKind::NotFound, "nothing was quarantined for this step")),
+    }
+}
+
+/// Whether the path still holds the link the step made, rather than
+/// anything written there since.
+fn is_own_link(entry: &JournalEntry) -> bool {
+    let path = &entry.step.path;
+    match (&entry.step.operation, &entry.link_target) {
+        (Operation::Hardlink { keeper, .. }, _) => same_file(path, keeper),
+        (Operation::Symlink { .. }, Some(target)) => fs::read_link(path).is_ok_and(|link| &link == target),
+        // Recorded before link targets were
+        (Operation::Symlink { keeper, .. }, None) => {
+            fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+                && fs::canonicalize(path).ok() == fs::canonicalize(keeper).ok()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_undo_restores_every_operation() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let keeper = root.join("keeper");
+        let deleted = root.join("deleted");
+        let linked = root.join("linked");
+        let moved = root.join("moved");
+        for path in [&keeper, &deleted, &linked, &moved] {
+            fs::write(path, b"shared").unwrap();
+        }
+
+        let mut plan = ActionPlan::default();
+        plan.push(&deleted, &keeper, Operation::Delete, 6, &[]);
+        plan.push(&linked, &keeper, Operation::Hardlink { keeper: keeper.clone(), preserve_permissions: false }, 6, &[]);
+        plan.push(&moved, &keeper, Operation::Move { destination: root.join("out").join("moved") }, 6, &[]);
+
+        let mut journal = Journal::open(&root.join("journal")).unwrap();
+        let outcome = journal.execute(&plan).unwrap();
+        assert!(outcome.errors.is_empty());
+        assert_eq!(outcome.reclaimed, 12);
+        assert!(!deleted.exists());
+        assert_eq!(journal.quarantine_size(), 12);
+
+        // The journal survives a restart
+        let mut journal = Journal::open(&root.join("journal")).unwrap();
+        let id = journal.batches()[0].id;
+        assert!(journal.undo(id).unwrap().is_empty());
+        assert_eq!(fs::read(&deleted).unwrap(), b"shared");
+        // The restored duplicate is its own file again
+        fs::write(&linked, b"edited").unwrap();
+        assert_eq!(fs::read(&keeper).unwrap(), b"shared");
+        assert_eq!(fs::read(&moved).unwrap(), b"shared");
+        assert!(!root.join("out").join("moved").exists());
+        assert!(journal.batches()[0].is_undone());
+        assert!(journal.undo(id).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_restores_the_keepers_permissions() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let temp_dir = tempdir().unwrap();
+        let keeper = temp_dir.path().join("keeper");
+        let linked = temp_dir.path().join("linked");
+        fs::write(&keeper, b"same").unwrap();
+        fs::write(&linked, b"same").unwrap();
+        fs::set_permissions(&keeper, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::set_permissions(&linked, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let mut plan = ActionPlan::default();
+        plan.push(&linked, &keeper, Operation::Hardlink { keeper: keeper.clone(), preserve_permissions: true }, 4, &[]);
+        let mut journal = Journal::open(&temp_dir.path().join("journal")).unwrap();
+        assert!(journal.execute(&plan).unwrap().errors.is_empty());
+        assert_eq!(fs::metadata(&keeper).unwrap().mode() & 0o777, 0o600);
+
+        assert!(journal.undo(1).unwrap().is_empty());
+        assert_eq!(fs::metadata(&keeper).unwrap().mode() & 0o777, 0o644);
+        assert_eq!(fs::metadata(&linked).unwrap().mode() & 0o777, 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_undo_keeps_files_written_since() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let keeper = root.join("keeper");
+        let linked = root.join("linked");
+        let symlinked = root.join("symlinked");
+        for path in [&keeper, &linked, &symlinked] {
+            fs::write(path, b"same").unwrap();
+        }
+
+        let mut plan = ActionPlan::default();
+        plan.push(&linked, &keeper, Operation::Hardlink { keeper: keeper.clone(), preserve_permissions: false }, 4, &[]);
+        plan.push(&symlinked, &keeper, Operation::Symlink { keeper: keeper.clone(), relative: true }, 4, &[]);
+        let mut journal = Journal::open(&root.join("journal")).unwrap();
+        assert!(journal.execute(&plan).unwrap().errors.is_empty());
+
+        // Both links are replaced by new work after the batch
+        fs::remove_file(&linked).unwrap();
+        fs::write(&linked, b"new work").unwrap();
+        fs::remove_file(&symlinked).unwrap();
+        std::os::unix::fs::symlink(&linked, &symlinked).unwrap();
+
+        assert_eq!(journal.undo(1).unwrap().len(), 2);
+        assert_eq!(fs::read(&linked).unwrap(), b"new work");
+        assert_eq!(fs::read_link(&symlinked).unwrap(), linked);
+        assert!(!journal.batches()[0].is_undone());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_device_quarantines_are_private_and_never_reused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let top = temp_dir.path();
+        let first = batch_dir(top, 3).unwrap();
+        let second = batch_dir(top, 3).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), second.parent());
+
+        // Shared with other users, so it is refused
+        let root = first.parent().unwrap();
+        fs::set_permissions(root, fs::Permissions::from_mode(0o777)).unwrap();
+        assert_eq!(batch_dir(top, 4).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+
+        // Files on the journal's own file system stay in its quarantine
+        let home = top.join("home");
+        fs::create_dir(&home).unwrap();
+        fs::write(top.join("file"), b"data").unwrap();
+        let mut quarantines = Quarantines::new(1, &home);
+        assert_eq!(quarantines.for_file(&top.join("file")).unwrap(), home);
+    }
+
+    #[test]
+    fn test_interrupted_batches_are_recovered() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        let dir = root.join("journal");
+        let keeper = root.join("keeper");
+        let copy = root.join("copy");
+        fs::write(&keeper, b"same").unwrap();
+        fs::write(&copy, b"same").unwrap();
+
+        // Left behind by a batch that was never recorded
+        let orphan = dir.join(QUARANTINE_DIR).join("1").join("0");
+        fs::create_dir_all(orphan.parent().unwrap()).unwrap();
+        fs::write(&orphan, b"orphan").unwrap();
+
+        let mut plan = ActionPlan::default();
+        plan.push(&copy, &keeper, Operation::Delete, 4, &[]);
+        let mut journal = Journal::open(&dir).unwrap();
+        assert!(journal.execute(&plan).unwrap().errors.is_empty());
+        assert_eq!(journal.batches()[0].id, 2);
+        assert_eq!(fs::read(&orphan).unwrap(), b"orphan");
+
+        // Simulate a crash before the journal was saved: only the log is left
+        let batch = journal.batches()[0].clone();
+        let mut log = serde_json::to_string(&JournalBatch { entries: Vec::new(), ..batch.clone() }).unwrap();
+        for entry in &batch.entries {
+            log += &format!("\n{}", serde_json::to_string(entry).unwrap());
+        }
+        log += "\n{\"step\":";
+        fs::write(dir.join(format!("2.{}", PENDING_EXT)), log).unwrap();
+        fs::remove_file(dir.join(JOURNAL_FILE)).unwrap();
+
+        let mut journal = Journal::open(&dir).unwrap();
+        assert_eq!(journal.batches(), [batch]);
+        assert!(!dir.join(format!("2.{}", PENDING_EXT)).exists());
+        assert!(journal.undo(2).unwrap().is_empty());
+        assert_eq!(fs::read(&copy).unwrap(), b"same");
+    }
+
+    #[test]
+    fn test_purged_batches_cannot_be_undone() {
+        let temp_dir = tempdir().unwrap();
+        let keeper = temp_dir.path().join("keeper");
+        let path = temp_dir.path().join("copy");
+        fs::write(&keeper, b"same").unwrap();
+        fs::write(&path, b"same").unwrap();
+
+        let mut plan = ActionPlan::default();
+        plan.push(&path, &keeper, Operation::Delete, 4, &[]);
+        // Gone by the time the plan runs, so the step is refused
+        plan.push(&temp_dir.path().join("missing"), &keeper, Operation::Delete, 4, &[]);
+
+        let mut journal = Journal::open(&temp_dir.path().join("journal")).unwrap();
+        let outcome = journal.execute(&plan).unwrap();
+        assert_eq!(outcome.errors.len(), 1);
+        assert_eq!(journal.batches()[0].entries.len(), 1);
+
+        journal.purge().unwrap();
+        assert!(!temp_dir.path().join("journal").join(format!("{}.tmp", JOURNAL_FILE)).exists());
+        assert_eq!(journal.quarantine_size(), 0);
+        assert!(!journal.batches()[0].can_undo());
+        assert!(!temp_dir.path().join("journal").join(QUARANTINE_DIR).exists());
+    }
+}
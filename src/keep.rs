@@ -0,0 +1,175 @@
+use std::{
+    cmp::Reverse,
+    io,
+    path::PathBuf,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::file_utils::{DuplicateGroup, DuplicateMember};
+
+/// How the copy to keep is chosen in every duplicate group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeepRule {
+    #[default]
+    Oldest,
+    Newest,
+    ShortestPath,
+    LongestPath,
+    FewestComponents,
+    /// The first member under the earliest listed preferred directory.
+    PreferredDirectories,
+    /// The first member whose path matches the pattern.
+    MatchingPattern,
+}
+
+impl KeepRule {
+    pub const ALL: [KeepRule; 7] = [
+        KeepRule::Oldest,
+        KeepRule::Newest,
+        KeepRule::ShortestPath,
+        KeepRule::LongestPath,
+        KeepRule::FewestComponents,
+        KeepRule::PreferredDirectories,
+        KeepRule::MatchingPattern,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            KeepRule::Oldest => "Oldest modification time",
+            KeepRule::Newest => "Newest modification time",
+            KeepRule::ShortestPath => "Shortest path",
+            KeepRule::LongestPath => "Longest path",
+            KeepRule::FewestComponents => "Fewest path components",
+            KeepRule::PreferredDirectories => "Preferred directories",
+            KeepRule::MatchingPattern => "Path matching a regex",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeepPolicy {
+    pub rule: KeepRule,
+    /// Used by [`KeepRule::PreferredDirectories`], highest priority first.
+    pub preferred_dirs: Vec<PathBuf>,
+    /// Used by [`KeepRule::MatchingPattern`].
+    pub pattern: String,
+}
+
+impl KeepPolicy {
+    /// Marks the keeper of every group. Reference copies are always
+    /// preferred; among equally good candidates the first by path wins.
+    pub fn apply(&self, groups: &mut [DuplicateGroup]) -> io::Result<()> {
+        let pattern = match self.rule {
+            KeepRule::MatchingPattern => Some(
+                Regex::new(&self.pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            ),
+            _ => None,
+        };
+        for group in groups {
+            group.keeper = self.keeper(&group.members, pattern.as_ref());
+        }
+        Ok(())
+    }
+
+    fn keeper(&self, members: &[DuplicateMember], pattern: Option<&Regex>) -> usize {
+        let has_reference = members.iter().any(|member| member.reference);
+        let candidates = members.iter()
+            .enumerate()
+            .filter(|(_, member)| member.reference || !has_reference);
+
+        // `min_by_key` keeps the first of equal elements, which is path
+        // order. Unknown modification times lose against known ones.
+        let best = match self.rule {
+            KeepRule::Oldest => candidates.min_by_key(|(_, member)| (member.modified.is_none(), member.modified)),
+            KeepRule::Newest => candidates.min_by_key(|(_, member)| (member.modified.is_none(), Reverse(member.modified))),
+            KeepRule::ShortestPath => candidates.min_by_key(|(_, member)| member.path.as_os_str().len()),
+            KeepRule::LongestPath => candidates.min_by_key(|(_, member)| Reverse(member.path.as_os_str().len())),
+            KeepRule::FewestComponents => candidates.min_by_key(|(_, member)| member.path.components().count()),
+            KeepRule::PreferredDirectories => candidates.min_by_key(|(_, member)| {
+                self.preferred_dirs.iter()
+                    .position(|dir| member.path.starts_with(dir))
+                    .unwrap_or(usize::MAX)
+            }),
+            KeepRule::MatchingPattern => candidates.min_by_key(|(_, member)| {
+                !pattern.is_some_and(|pattern| pattern.is_match(&member.path.to_string_lossy()))
+            }),
+        };
+        best.map(|(idx, _)| idx).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn group(members: &[(&str, u64, bool)]) -> DuplicateGroup {
+        DuplicateGroup {
+            id: 0,
+            size: 1,
+            hash: Vec::new(),
+            keeper: 0,
+            members: members.iter()
+                .map(|&(path, age, reference)| DuplicateMember {
+                    path: PathBuf::from(path),
+                    modified: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(age)),
+                    root: 0,
+                    reference,
+                })
+                .collect(),
+        }
+    }
+
+    fn keeper(policy: KeepPolicy, members: &[(&str, u64, bool)]) -> usize {
+        let mut groups = vec![group(members)];
+        policy.apply(&mut groups).unwrap();
+        groups[0].keeper
+    }
+
+    #[test]
+    fn test_keep_rules() {
+        let members = [
+            ("/archive/2020/photos/a.jpg", 300, false),
+            ("/import/a.jpg", 100, false),
+            ("/tmp/very/deep/copy/of/a.jpg", 200, false),
+        ];
+        let rule = |rule| KeepPolicy { rule, ..Default::default() };
+
+        assert_eq!(keeper(rule(KeepRule::Oldest), &members), 1);
+        assert_eq!(keeper(rule(KeepRule::Newest), &members), 0);
+        assert_eq!(keeper(rule(KeepRule::ShortestPath), &members), 1);
+        assert_eq!(keeper(rule(KeepRule::LongestPath), &members), 2);
+        assert_eq!(keeper(rule(KeepRule::FewestComponents), &members), 1);
+
+        let preferred = KeepPolicy {
+            rule: KeepRule::PreferredDirectories,
+            preferred_dirs: vec![PathBuf::from("/archive")],
+            ..Default::default()
+        };
+        assert_eq!(keeper(preferred, &members), 0);
+
+        let pattern = KeepPolicy {
+            rule: KeepRule::MatchingPattern,
+            pattern: "/deep/".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(keeper(pattern, &members), 2);
+    }
+
+    #[test]
+    fn test_reference_copies_are_kept() {
+        let members = [("/archive/a.jpg", 300, true), ("/import/a.jpg", 100, false)];
+        assert_eq!(keeper(KeepPolicy::default(), &members), 0);
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_rejected() {
+        let policy = KeepPolicy {
+            rule: KeepRule::MatchingPattern,
+            pattern: "(".to_string(),
+            ..Default::default()
+        };
+        assert!(policy.apply(&mut []).is_err());
+    }
+}
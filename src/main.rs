@@ -1,8 +1,22 @@
 mod app;
+mod batch;
+mod config;
 mod file_scanner;
 mod file_utils;
+mod filters;
+mod hash_cache;
+mod hasher;
+mod ignore_files;
+mod journal;
+mod keep;
+mod plan;
 mod preview;
+mod problems;
+mod reflink;
+mod report;
+mod scan_handle;
 mod tests;
+mod trash;
 
 use app::DuplicateFinderApp;
 
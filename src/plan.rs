@@ -0,0 +1,282 @@
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use crate::{
+    file_utils::{
+        files_equal, format_hash, move_file, rename_noreplace, replace_with_hardlink, replace_with_symlink,
+        DEFAULT_CHUNK_SIZE,
+    },
+    reflink,
+    scan_handle::ScanHandle,
+    trash::trash,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Operation {
+    Delete,
+    /// Move to the desktop trash, restorable from the file manager.
+    Trash,
+    /// Replace the file with a hard link to `keeper`.
+    Hardlink {
+        keeper: PathBuf,
+        /// Give the linked file the duplicate's mode and ownership.
+        #[serde(default)]
+        preserve_permissions: bool,
+    },
+    /// Share the extents of `keeper`, keeping an independent file.
+    Reflink { keeper: PathBuf },
+    /// Replace the file with a symbolic link to `keeper`.
+    Symlink {
+        keeper: PathBuf,
+        /// Point the link at the keeper relative to the link's directory.
+        #[serde(default)]
+        relative: bool,
+    },
+    Move { destination: PathBuf },
+}
+
+impl Operation {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Operation::Delete => "Delete",
+            Operation::Trash => "Trash",
+            Operation::Hardlink { .. } => "Hardlink",
+            Operation::Reflink { .. } => "Reflink",
+            Operation::Symlink { .. } => "Symlink",
+            Operation::Move { .. } => "Move",
+        }
+    }
+
+    fn keeper(&self) -> Option<&Path> {
+        match self {
+            Operation::Hardlink { keeper, .. } | Operation::Reflink { keeper } | Operation::Symlink { keeper, .. } => {
+                Some(keeper)
+            }
+            Operation::Delete | Operation::Trash | Operation::Move { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanStep {
+    pub path: PathBuf,
+    /// The copy that stays. A step only runs while it still matches.
+    #[serde(default)]
+    pub kept_copy: Option<PathBuf>,
+    #[serde(flatten)]
+    pub operation: Operation,
+    /// Size of the file when the plan was made.
+    pub size: u64,
+    /// Content hash of the duplicate group, in hex.
+    #[serde(default)]
+    pub hash: String,
+}
+
+impl PlanStep {
+    /// Bytes freed by this step. Moved files still take up space.
+    pub fn reclaimed(&self) -> u64 {
+        match self.operation {
+            Operation::Move { .. } => 0,
+            _ => self.size,
+        }
+    }
+
+    /// Refuses the step unless the kept copy is still there with the same
+    /// content. Linking steps compare the content as part of the
+    /// replacement, reflinks have the kernel do it.
+    fn check_keeper(&self) -> io::Result<()> {
+        let keeper = self.kept_copy.as_deref()
+            .or(self.operation.keeper())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the plan does not name the kept copy"))?;
+        if keeper == self.path {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the file is its own kept copy"));
+        }
+        if !fs::metadata(keeper).is_ok_and(|m| m.is_file()) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "the kept copy is gone"));
+        }
+        match self.operation {
+            Operation::Hardlink { .. } | Operation::Reflink { .. } | Operation::Symlink { .. } => Ok(()),
+            Operation::Delete | Operation::Trash | Operation::Move { .. } => {
+                if files_equal(keeper, &self.path, DEFAULT_CHUNK_SIZE, &ScanHandle::new())? {
+                    Ok(())
+                } else {
+                    Err(io::Error::new(io::ErrorKind::InvalidData, "content no longer matches the kept copy"))
+                }
+            }
+        }
+    }
+
+    /// Performs the step. Files that are replaced or deleted are moved to
+    /// `quarantine` rather than removed; returns where the file went, unless
+    /// it was moved to a chosen destination.
+    pub fn run(&self, quarantine: &Path) -> io::Result<Option<PathBuf>> {
+        // A plan may be executed long after it was made
+        let metadata = fs::symlink_metadata(&self.path)?;
+        if !metadata.is_file() || metadata.len() != self.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "file changed since the plan was made"));
+        }
+        self.check_keeper()?;
+
+        match &self.operation {
+            Operation::Move { destination } => return move_file(&self.path, destination).map(|_| None),
+            Operation::Trash => return trash(&self.path).map(Some),
+            // Done in place, the file keeps its inode and content
+            Operation::Reflink { keeper } => return reflink::dedupe(keeper, &self.path).map(|_| None),
+            Operation::Delete => return quarantine_file(&self.path, quarantine).map(|_| Some(quarantine.to_path_buf())),
+            Operation::Hardlink { .. } | Operation::Symlink { .. } => {}
+        }
+
+        // Keep the duplicate's content before it is replaced in place. The
+        // quarantine is on the same file system, so a copy is a last resort
+        // for file systems without hard links and never crosses disks.
+        if let Some(parent) = quarantine.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::hard_link(&self.path, quarantine).or_else(|e| match e.kind() {
+            io::ErrorKind::CrossesDevices => Err(not_on_device()),
+            _ => fs::copy(&self.path, quarantine).map(|_| ()),
+        })?;
+        let replaced = match &self.operation {
+            Operation::Hardlink { keeper, preserve_permissions } => {
+                replace_with_hardlink(keeper, &self.path, *preserve_permissions)
+            }
+            Operation::Symlink { keeper, relative } => replace_with_symlink(keeper, &self.path, *relative),
+            _ => Ok(()),
+        };
+        if let Err(e) = replaced {
+            let _ = fs::remove_file(quarantine);
+            return Err(e);
+        }
+        Ok(Some(quarantine.to_path_buf()))
+    }
+}
+
+/// Moves a file into its quarantine, which is on the same file system.
+/// Files are never copied there.
+fn quarantine_file(path: &Path, quarantine: &Path) -> io::Result<()> {
+    if let Some(parent) = quarantine.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    rename_noreplace(path, quarantine).map_err(|e| match e.kind() {
+        io::ErrorKind::CrossesDevices => not_on_device(),
+        _ => e,
+    })
+}
+
+fn not_on_device() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::CrossesDevices,
+        "the quarantine is on another file system, nothing was copied or changed",
+    )
+}
+
+/// An ordered list of file operations, reviewed before anything is touched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionPlan {
+    pub created_at: DateTime<Local>,
+    pub steps: Vec<PlanStep>,
+}
+
+#[derive(Debug, Default)]
+pub struct PlanOutcome {
+    /// Paths of the steps that succeeded.
+    pub done: Vec<PathBuf>,
+    pub reclaimed: u64,
+    pub errors: Vec<String>,
+}
+
+impl Default for ActionPlan {
+    fn default() -> Self {
+        Self {
+            created_at: Local::now(),
+            steps: Vec::new(),
+        }
+    }
+}
+
+impl ActionPlan {
+    pub fn push(&mut self, path: &Path, keeper: &Path, operation: Operation, size: u64, hash: &[u8]) {
+        self.steps.push(PlanStep {
+            path: path.to_path_buf(),
+            kept_copy: Some(keeper.to_path_buf()),
+            operation,
+            size,
+            hash: format_hash(hash),
+        });
+    }
+
+    pub fn reclaimed(&self) -> u64 {
+        self.steps.iter().map(PlanStep::reclaimed).sum()
+    }
+
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents)
+    }
+
+    pub fn read_from(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan_roundtrip() {
+        let temp_dir = tempdir().unwrap();
+        let copy = temp_dir.path().join("copy");
+        fs::write(&copy, b"same").unwrap();
+
+        let mut plan = ActionPlan::default();
+        let keeper = temp_dir.path().join("keeper");
+        let operation = Operation::Hardlink { keeper: keeper.clone(), preserve_permissions: false };
+        plan.push(&copy, &keeper, operation, 4, &[0xab, 0x01]);
+        plan.push(&copy, &keeper, Operation::Move { destination: temp_dir.path().join("out") }, 4, &[0xab, 0x01]);
+        assert_eq!(plan.reclaimed(), 4);
+
+        // Making and exporting the plan touches nothing
+        let exported = temp_dir.path().join("plan.json");
+        plan.write_to(&exported).unwrap();
+        assert!(copy.exists());
+
+        let loaded = ActionPlan::read_from(&exported).unwrap();
+        assert_eq!(loaded.steps, plan.steps);
+        assert_eq!(loaded.steps[0].hash, "ab01");
+    }
+
+    #[test]
+    fn test_steps_check_the_kept_copy() {
+        let temp_dir = tempdir().unwrap();
+        let keeper = temp_dir.path().join("keeper");
+        let copy = temp_dir.path().join("copy");
+        fs::write(&keeper, b"same").unwrap();
+        fs::write(&copy, b"same").unwrap();
+        let quarantine = temp_dir.path().join("quarantine");
+
+        let mut plan = ActionPlan::default();
+        plan.push(&copy, &keeper, Operation::Delete, 4, &[]);
+        let step = &plan.steps[0];
+
+        // Edited without changing its size
+        fs::write(&copy, b"diff").unwrap();
+        assert_eq!(step.run(&quarantine).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::write(&copy, b"same").unwrap();
+        fs::remove_file(&keeper).unwrap();
+        assert_eq!(step.run(&quarantine).unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(fs::read(&copy).unwrap(), b"same");
+
+        fs::write(&keeper, b"same").unwrap();
+        assert_eq!(step.run(&quarantine).unwrap(), Some(quarantine));
+        assert!(!copy.exists());
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use serde::Serialize;
+use crate::scan_handle::ScanPhase;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ProblemKind {
+    PermissionDenied,
+    /// The file disappeared between being listed and being read.
+    Vanished,
+    SymlinkLoop,
+    Io,
+}
+
+impl ProblemKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProblemKind::PermissionDenied => "Permission denied",
+            ProblemKind::Vanished => "Vanished",
+            ProblemKind::SymlinkLoop => "Symlink loop",
+            ProblemKind::Io => "I/O error",
+        }
+    }
+
+    fn of(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => ProblemKind::PermissionDenied,
+            io::ErrorKind::NotFound => ProblemKind::Vanished,
+            _ => ProblemKind::Io,
+        }
+    }
+}
+
+/// A file or directory the scan had to skip, and why.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScanProblem {
+    pub path: Option<PathBuf>,
+    pub phase: ScanPhase,
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+impl ScanProblem {
+    pub fn io(path: Option<&Path>, phase: ScanPhase, err: &io::Error) -> Self {
+        Self {
+            path: path.map(Path::to_path_buf),
+            phase,
+            kind: ProblemKind::of(err),
+            message: err.to_string(),
+        }
+    }
+
+    pub fn walk(err: &walkdir::Error) -> Self {
+        let kind = match err.io_error() {
+            _ if err.loop_ancestor().is_some() => ProblemKind::SymlinkLoop,
+            Some(io_err) => ProblemKind::of(io_err),
+            None => ProblemKind::Io,
+        };
+        Self {
+            path: err.path().map(Path::to_path_buf),
+            phase: ScanPhase::Walking,
+            kind,
+            message: err.to_string(),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::{fs, io, path::Path};
+
+/// `_IOWR(0x94, 54, struct file_dedupe_range)`, the same on every
+/// architecture unlike `FICLONE`.
+#[cfg(target_os = "linux")]
+const FIDEDUPERANGE: u64 = 0xC018_9436;
+#[cfg(target_os = "linux")]
+const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FileDedupeRange {
+    src_offset: u64,
+    src_length: u64,
+    dest_count: u16,
+    reserved1: u16,
+    reserved2: u32,
+    info: [FileDedupeRangeInfo; 1],
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FileDedupeRangeInfo {
+    dest_fd: i64,
+    dest_offset: u64,
+    bytes_deduped: u64,
+    status: i32,
+    reserved: u32,
+}
+
+/// Whether the file system holding `sample` can share extents between
+/// files, as Btrfs and XFS with reflink enabled do. Probed by cloning
+/// `sample` into an unnamed temporary file next to it.
+#[cfg(target_os = "linux")]
+pub fn supported(sample: &Path) -> bool {
+    use std::{
+        ffi::CString,
+        os::unix::{ffi::OsStrExt, io::{AsRawFd, FromRawFd}},
+    };
+
+    let dir = sample.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let (Ok(source), Ok(dir)) = (fs::File::open(sample), CString::new(dir.as_os_str().as_bytes())) else {
+        return false;
+    };
+    let fd = unsafe { libc::open(dir.as_ptr(), libc::O_TMPFILE | libc::O_RDWR, 0o600) };
+    if fd < 0 {
+        return false;
+    }
+    // Closes the descriptor, which discards the unnamed file
+    let temp = unsafe { fs::File::from_raw_fd(fd) };
+    unsafe { libc::ioctl(temp.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn supported(_sample: &Path) -> bool {
+    false
+}
+
+/// Makes `duplicate` share the extents of `keeper`. The kernel checks the
+/// content is identical while doing so, and the duplicate stays a separate
+/// file: editing one copy later does not change the other.
+#[cfg(target_os = "linux")]
+pub fn dedupe(keeper: &Path, duplicate: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let source = fs::File::open(keeper)?;
+    let dest = fs::OpenOptions::new().write(true).open(duplicate)?;
+    let len = source.metadata()?.len();
+    if dest.metadata()?.len() != len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "content no longer matches the kept copy"));
+    }
+
+    // The kernel may share less than asked for in one call
+    let mut offset = 0;
+    while offset < len {
+        let mut range = FileDedupeRange {
+            src_offset: offset,
+            src_length: len - offset,
+            dest_count: 1,
+            reserved1: 0,
+            reserved2: 0,
+            info: [FileDedupeRangeInfo {
+                dest_fd: dest.as_raw_fd() as i64,
+                dest_offset: offset,
+                bytes_deduped: 0,
+                status: 0,
+                reserved: 0,
+            }],
+        };
+        if unsafe { libc::ioctl(source.as_raw_fd(), FIDEDUPERANGE as _, &mut range) } != 0 {
+            return Err(explain(io::Error::last_os_error()));
+        }
+        let info = &range.info[0];
+        if info.status == FILE_DEDUPE_RANGE_DIFFERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "content no longer matches the kept copy"));
+        }
+        if info.status < 0 {
+            return Err(explain(io::Error::from_raw_os_error(-info.status)));
+        }
+        if info.bytes_deduped == 0 {
+            return Err(io::Error::other("the file system stopped sharing extents"));
+        }
+        offset += info.bytes_deduped;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn dedupe(_keeper: &Path, _duplicate: &Path) -> io::Result<()> {
+    Err(unsupported())
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "this file system cannot share extents between files, use a hard link or symbolic link instead",
+    )
+}
+
+/// Explains the errors that mean reflinks are not available here.
+#[cfg(target_os = "linux")]
+fn explain(err: io::Error) -> io::Error {
+    match err.raw_os_error() {
+        Some(libc::EXDEV) => {
+            io::Error::new(io::ErrorKind::CrossesDevices, "reflinks cannot cross file systems, use a symbolic link instead")
+        }
+        Some(libc::EOPNOTSUPP | libc::ENOTTY | libc::EINVAL) => unsupported(),
+        _ => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_dedupe_or_explain() {
+        let temp_dir = tempdir().unwrap();
+        let keeper = temp_dir.path().join("keeper");
+        let duplicate = temp_dir.path().join("duplicate");
+        let content = vec![7u8; 256 * 1024];
+        fs::write(&keeper, &content).unwrap();
+        fs::write(&duplicate, &content).unwrap();
+
+        // Which branch runs depends on the file system of the temp dir
+        match dedupe(&keeper, &duplicate) {
+            Ok(()) => {
+                assert!(supported(&keeper));
+                assert_eq!(fs::read(&duplicate).unwrap(), content);
+                // The copies stay independent
+                fs::write(&duplicate, b"edited").unwrap();
+                assert_eq!(fs::read(&keeper).unwrap(), content);
+            }
+            Err(e) => {
+                assert!(!supported(&keeper));
+                assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+                assert_eq!(fs::read(&duplicate).unwrap(), content);
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use crate::{
+    file_utils::{format_hash, DeviceSummary, DuplicateMember, HashOptions, ScanResult, ScanRoot, StageStats},
+    filters::FilterOptions,
+    problems::ScanProblem,
+};
+
+/// JSON description of a finished scan, including every setting needed to
+/// reproduce it.
+#[derive(Serialize)]
+pub struct ScanReport<'a> {
+    pub generated_at: DateTime<Local>,
+    pub roots: &'a [ScanRoot],
+    pub filters: &'a FilterOptions,
+    pub hash: &'a HashOptions,
+    pub stages: &'a [StageStats],
+    pub devices: &'a [DeviceSummary],
+    pub groups: Vec<ReportGroup<'a>>,
+    pub collisions: Vec<ReportCollision<'a>>,
+    pub problems: &'a [ScanProblem],
+}
+
+#[derive(Serialize)]
+pub struct ReportGroup<'a> {
+    pub id: String,
+    pub hash: String,
+    pub size: u64,
+    pub wasted: u64,
+    pub keeper: &'a Path,
+    pub members: &'a [DuplicateMember],
+}
+
+#[derive(Serialize)]
+pub struct ReportCollision<'a> {
+    pub hash: String,
+    pub groups: &'a [Vec<PathBuf>],
+}
+
+impl<'a> ScanReport<'a> {
+    pub fn new(result: &'a ScanResult) -> Self {
+        let groups = result.duplicates.iter()
+            .map(|group| ReportGroup {
+                id: format!("{:016x}", group.id),
+                hash: format_hash(&group.hash),
+                size: group.size,
+                wasted: group.wasted(),
+                keeper: &group.members[group.keeper].path,
+                members: &group.members,
+            })
+            .collect();
+
+        Self {
+            generated_at: Local::now(),
+            roots: &result.roots,
+            filters: &result.filters,
+            hash: &result.options,
+            stages: &result.stages,
+            devices: &result.devices,
+            groups,
+            collisions: result.collisions.iter()
+                .map(|collision| ReportCollision { hash: format_hash(&collision.hash), groups: &collision.groups })
+                .collect(),
+            problems: &result.problems,
+        }
+    }
+
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file_utils::{collect_roots, find_duplicates, ScanContext},
+        filters::FileFilter,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_report_records_settings_and_groups() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), b"same").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), b"same").unwrap();
+
+        let mut options = FilterOptions::default();
+        options.constraints.min_size = Some(1);
+        let filter = FileFilter::new(&options).unwrap();
+        let roots = vec![ScanRoot::new(temp_dir.path(), false)];
+        let ctx = ScanContext::default();
+        let mut files = collect_roots(&roots, &filter, &ctx);
+        let mut result = find_duplicates(&mut files, &HashOptions::default(), &ctx);
+        result.roots = roots;
+        result.filters = options;
+
+        let path = temp_dir.path().join("report.json");
+        ScanReport::new(&result).write_to(&path).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        assert_eq!(report["filters"]["constraints"]["min_size"], 1);
+        assert_eq!(report["hash"]["algorithm"], "Sha256");
+        assert_eq!(report["groups"][0]["members"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    io,
+    sync::{Arc, Condvar, Mutex, atomic::{AtomicBool, AtomicU64, Ordering}},
+    time::{Duration, Instant},
+};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum ScanPhase {
+    #[default]
+    Walking,
+    SizeGrouping,
+    PartialHash,
+    FullHash,
+    Verifying,
+}
+
+impl ScanPhase {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ScanPhase::Walking => "Collecting files",
+            ScanPhase::SizeGrouping => "Grouping by size",
+            ScanPhase::PartialHash => "Partial hashing",
+            ScanPhase::FullHash => "Full hashing",
+            ScanPhase::Verifying => "Verifying",
+        }
+    }
+}
+
+/// Snapshot of a scan's progress within its current phase. Totals are zero
+/// while they are still unknown, e.g. during the walk.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanProgress {
+    pub phase: ScanPhase,
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// Bytes per second since the phase started.
+    pub throughput: f64,
+    pub eta: Option<Duration>,
+}
+
+impl ScanProgress {
+    pub fn fraction(&self) -> f32 {
+        let (done, total) = if self.bytes_total > 0 {
+            (self.bytes_done, self.bytes_total)
+        } else {
+            (self.files_done, self.files_total)
+        };
+        if total == 0 {
+            0.0
+        } else {
+            (done as f64 / total as f64).min(1.0) as f32
+        }
+    }
+}
+
+/// Shared control for a running scan. Clones refer to the same scan, so the
+/// GUI can keep one while the walker and hashing workers poll another.
+#[derive(Clone, Default)]
+pub struct ScanHandle {
+    state: Arc<HandleState>,
+}
+
+#[derive(Default)]
+struct HandleState {
+    cancelled: AtomicBool,
+    paused: AtomicBool,
+    lock: Mutex<()>,
+    changed: Condvar,
+    phase: Mutex<(ScanPhase, Option<Instant>)>,
+    files_done: AtomicU64,
+    files_total: AtomicU64,
+    bytes_done: AtomicU64,
+    bytes_total: AtomicU64,
+}
+
+impl ScanHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        self.notify();
+    }
+
+    pub fn pause(&self) {
+        self.state.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.state.paused.store(false, Ordering::SeqCst);
+        self.notify();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.load(Ordering::SeqCst)
+    }
+
+    /// Blocks while the scan is paused. Returns `false` once it has been
+    /// cancelled, in which case the caller should stop working.
+    pub fn checkpoint(&self) -> bool {
+        if self.is_paused() && !self.is_cancelled() {
+            let mut guard = self.state.lock.lock().unwrap();
+            while self.is_paused() && !self.is_cancelled() {
+                guard = self.state.changed.wait(guard).unwrap();
+            }
+        }
+        !self.is_cancelled()
+    }
+
+    /// Like `checkpoint`, but as an error for use inside I/O loops.
+    pub fn check(&self) -> io::Result<()> {
+        if self.checkpoint() {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::Interrupted, "scan cancelled"))
+        }
+    }
+
+    /// Enters `phase` and resets the counters. Pass zero for unknown totals.
+    pub fn start_phase(&self, phase: ScanPhase, files_total: u64, bytes_total: u64) {
+        *self.state.phase.lock().unwrap() = (phase, Some(Instant::now()));
+        self.state.files_done.store(0, Ordering::SeqCst);
+        self.state.bytes_done.store(0, Ordering::SeqCst);
+        self.state.files_total.store(files_total, Ordering::SeqCst);
+        self.state.bytes_total.store(bytes_total, Ordering::SeqCst);
+    }
+
+    pub fn add_files(&self, count: u64) {
+        self.state.files_done.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, count: u64) {
+        self.state.bytes_done.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn progress(&self) -> ScanProgress {
+        let (phase, started) = *self.state.phase.lock().unwrap();
+        let files_done = self.state.files_done.load(Ordering::Relaxed);
+        let files_total = self.state.files_total.load(Ordering::Relaxed);
+        let bytes_done = self.state.bytes_done.load(Ordering::Relaxed);
+        let bytes_total = self.state.bytes_total.load(Ordering::Relaxed);
+
+        let elapsed = started.map_or(0.0, |started| started.elapsed().as_secs_f64());
+        let throughput = if elapsed > 0.0 { bytes_done as f64 / elapsed } else { 0.0 };
+
+        // Estimate from bytes where they are known, otherwise from files
+        let (done, total) = if bytes_total > 0 { (bytes_done, bytes_total) } else { (files_done, files_total) };
+        let eta = if done > 0 && total > done && elapsed > 0.0 {
+            let rate = done as f64 / elapsed;
+            Some(Duration::from_secs_f64((total - done) as f64 / rate))
+        } else {
+            None
+        };
+
+        ScanProgress { phase, files_done, files_total, bytes_done, bytes_total, throughput, eta }
+    }
+
+    fn notify(&self) {
+        let _guard = self.state.lock.lock().unwrap();
+        self.state.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_pause_blocks_until_resume() {
+        let handle = ScanHandle::new();
+        handle.pause();
+
+        let worker = {
+            let handle = handle.clone();
+            thread::spawn(move || handle.checkpoint())
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!worker.is_finished());
+
+        handle.resume();
+        assert!(worker.join().unwrap());
+    }
+
+    #[test]
+    fn test_cancel_releases_paused_workers() {
+        let handle = ScanHandle::new();
+        handle.pause();
+
+        let worker = {
+            let handle = handle.clone();
+            thread::spawn(move || handle.checkpoint())
+        };
+        thread::sleep(Duration::from_millis(50));
+        handle.cancel();
+
+        assert!(!worker.join().unwrap());
+        assert!(handle.check().is_err());
+    }
+
+    #[test]
+    fn test_progress_counts_within_phase() {
+        let handle = ScanHandle::new();
+        handle.start_phase(ScanPhase::FullHash, 4, 1000);
+        handle.add_files(1);
+        handle.add_bytes(250);
+
+        let progress = handle.progress();
+        assert_eq!(progress.phase, ScanPhase::FullHash);
+        assert_eq!(progress.files_done, 1);
+        assert_eq!(progress.fraction(), 0.25);
+
+        // A new phase starts from zero
+        handle.start_phase(ScanPhase::Verifying, 2, 0);
+        let progress = handle.progress();
+        assert_eq!(progress.bytes_done, 0);
+        assert_eq!(progress.fraction(), 0.0);
+        assert!(progress.eta.is_none());
+    }
+}
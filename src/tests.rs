@@ -4,8 +4,9 @@ mod tests {
     use std::{fs, io::Write, path::PathBuf};
     use tempfile::TempDir;
     use crate::{
-        file_utils::{FileInfo, find_duplicates},
+        file_utils::{FileInfo, HashOptions, ScanContext, ScanRoot, find_duplicates},
         file_scanner::Scanner,
+        filters::FileFilter,
         preview::Preview,
     };
 
@@ -56,16 +57,16 @@ mod tests {
             .map(|p| FileInfo::new(p.clone()).unwrap())
             .collect();
         
-        let duplicates = find_duplicates(&mut files);
+        let duplicates = find_duplicates(&mut files, &HashOptions::default(), &ScanContext::default()).duplicates;
         
         // Should find 2 groups of duplicates
         assert_eq!(duplicates.len(), 2);
         
         // First group should have 3 files
-        assert!(duplicates.values().any(|group| group.len() == 3));
+        assert!(duplicates.iter().any(|group| group.members.len() == 3));
         
         // Second group should have 2 files
-        assert!(duplicates.values().any(|group| group.len() == 2));
+        assert!(duplicates.iter().any(|group| group.members.len() == 2));
     }
 
     #[test]
@@ -75,7 +76,7 @@ mod tests {
         let scanner = Scanner::new();
         let receiver = scanner.receiver();
         
-        scanner.start_scan(temp_dir.path(), vec![]);
+        scanner.start_scan(&[ScanRoot::new(temp_dir.path(), false)], FileFilter::default(), HashOptions::default());
         
         let mut found_duplicates = false;
         let mut progress_reported = false;
@@ -83,17 +84,22 @@ mod tests {
         while let Ok(message) = receiver.recv_timeout(std::time::Duration::from_secs(5)) {
             match message {
                 crate::file_scanner::ScannerMessage::Progress(progress) => {
-                    assert!(progress >= 0.0 && progress <= 1.0);
+                    let fraction = progress.fraction();
+                    assert!(fraction >= 0.0 && fraction <= 1.0);
                     progress_reported = true;
                 }
-                crate::file_scanner::ScannerMessage::Found(duplicates) => {
-                    assert_eq!(duplicates.len(), 2); // Should find our 2 groups
+                crate::file_scanner::ScannerMessage::Found(result) => {
+                    assert_eq!(result.duplicates.len(), 2); // Should find our 2 groups
                     found_duplicates = true;
                     break;
                 }
                 crate::file_scanner::ScannerMessage::Error(e) => {
                     panic!("Scanner error: {}", e);
                 }
+                crate::file_scanner::ScannerMessage::Problem(problem) => {
+                    panic!("Unexpected problem: {:?}", problem);
+                }
+                crate::file_scanner::ScannerMessage::Changed => {}
             }
         }
         
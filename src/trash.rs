@@ -0,0 +1,192 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+use crate::file_utils::move_file;
+
+/// Moves `path` to the trash as described by the freedesktop.org trash
+/// specification, so it can be restored from the file manager. Returns
+/// where the file now lives inside the trash.
+pub fn trash(path: &Path) -> io::Result<PathBuf> {
+    match dirs::data_dir() {
+        Some(dir) => trash_in(path, &dir.join("Trash")),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "no data directory available")),
+    }
+}
+
+/// Moves a trashed file back to `path` and drops its trash info.
+pub fn restore(trashed: &Path, path: &Path) -> io::Result<()> {
+    move_file(trashed, path)?;
+    if let (Some(trash_dir), Some(name)) = (trashed.parent().and_then(Path::parent), trashed.file_name()) {
+        let _ = fs::remove_file(trash_dir.join("info").join(info_name(name)));
+    }
+    Ok(())
+}
+
+fn info_name(name: &OsStr) -> OsString {
+    let mut info = name.to_os_string();
+    info.push(".trashinfo");
+    info
+}
+
+#[cfg(unix)]
+fn trash_in(path: &Path, home_trash: &Path) -> io::Result<PathBuf> {
+    use std::{io::Write, os::unix::fs::MetadataExt};
+    use chrono::Local;
+
+    let name = path.file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let path = parent.canonicalize()?.join(name);
+    let device = fs::symlink_metadata(&path)?.dev();
+
+    // The home trash is used for files on its own file system, every other
+    // mount has a trash directory at its top
+    let home_device = home_trash.ancestors().find_map(|dir| fs::metadata(dir).ok()).map(|m| m.dev());
+    let (trash_dir, info_path) = if home_device == Some(device) {
+        (home_trash.to_path_buf(), path.clone())
+    } else {
+        let top = mount_top(&path, device);
+        let uid = unsafe { libc::getuid() };
+        // Paths in a per-mount trash are relative to the mount
+        (topdir_trash(&top, uid)?, path.strip_prefix(&top).unwrap_or(&path).to_path_buf())
+    };
+    fs::create_dir_all(trash_dir.join("files"))?;
+    fs::create_dir_all(trash_dir.join("info"))?;
+
+    let contents = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        encode(&info_path),
+        Local::now().format("%Y-%m-%dT%H:%M:%S"),
+    );
+    let mut attempt = 1;
+    loop {
+        let mut entry = name.to_os_string();
+        if attempt > 1 {
+            entry.push(format!(".{}", attempt));
+        }
+        attempt += 1;
+
+        // Creating the info file exclusively reserves the name
+        let info = trash_dir.join("info").join(info_name(&entry));
+        let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        let target = trash_dir.join("files").join(&entry);
+        if fs::symlink_metadata(&target).is_ok() {
+            let _ = fs::remove_file(&info);
+            continue;
+        }
+        let result = file.write_all(contents.as_bytes()).and_then(|_| fs::rename(&path, &target));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&info);
+            return Err(e);
+        }
+        return Ok(target);
+    }
+}
+
+#[cfg(not(unix))]
+fn trash_in(_path: &Path, _home_trash: &Path) -> io::Result<PathBuf> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "the trash is only supported on Unix desktops"))
+}
+
+/// The outermost directory above `path` that is still on `device`.
+#[cfg(unix)]
+pub fn mount_top(path: &Path, device: u64) -> PathBuf {
+    use std::os::unix::fs::MetadataExt;
+
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| fs::metadata(dir).is_ok_and(|m| m.dev() == device))
+        .last()
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+/// `$top/.Trash/$uid` if the administrator set up a shared, sticky
+/// `.Trash`, otherwise `$top/.Trash-$uid`.
+#[cfg(unix)]
+fn topdir_trash(top: &Path, uid: u32) -> io::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let shared = top.join(".Trash");
+    let usable = fs::symlink_metadata(&shared)
+        .is_ok_and(|m| m.is_dir() && m.permissions().mode() & 0o1000 != 0);
+    if usable {
+        let dir = shared.join(uid.to_string());
+        if dir.is_dir() || fs::DirBuilder::new().mode(0o700).create(&dir).is_ok() {
+            return Ok(dir);
+        }
+    }
+
+    let dir = top.join(format!(".Trash-{}", uid));
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists || !dir.is_dir() => Err(e),
+        _ => Ok(dir),
+    }
+}
+
+/// Percent-encodes a path for the `Path` key of a trash info file.
+#[cfg(unix)]
+fn encode(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str().as_bytes().iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_trash_and_restore() {
+        let temp_dir = tempdir().unwrap();
+        let home_trash = temp_dir.path().join("Trash");
+        let path = temp_dir.path().canonicalize().unwrap().join("my file.txt");
+        fs::write(&path, b"duplicate").unwrap();
+
+        let trashed = trash_in(&path, &home_trash).unwrap();
+        assert_eq!(trashed, home_trash.join("files").join("my file.txt"));
+        assert!(!path.exists());
+        let info = fs::read_to_string(home_trash.join("info").join("my file.txt.trashinfo")).unwrap();
+        assert!(info.starts_with("[Trash Info]\n"));
+        assert!(info.contains(&format!("Path={}\n", encode(&path))));
+        assert!(info.contains("my%20file.txt"));
+        assert!(info.contains("DeletionDate="));
+
+        // A second file with the same name gets a new entry
+        fs::write(&path, b"duplicate").unwrap();
+        let second = trash_in(&path, &home_trash).unwrap();
+        assert_eq!(second, home_trash.join("files").join("my file.txt.2"));
+
+        restore(&trashed, &path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"duplicate");
+        assert!(!home_trash.join("info").join("my file.txt.trashinfo").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_topdir_trash_requires_sticky_shared_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let top = temp_dir.path();
+        fs::create_dir(top.join(".Trash")).unwrap();
+        assert_eq!(topdir_trash(top, 1000).unwrap(), top.join(".Trash-1000"));
+
+        fs::set_permissions(top.join(".Trash"), fs::Permissions::from_mode(0o1777)).unwrap();
+        assert_eq!(topdir_trash(top, 1000).unwrap(), top.join(".Trash").join("1000"));
+    }
+}